@@ -0,0 +1,135 @@
+// criterionはwasm32では使えないので、wasm32では空のベンチになる
+#[cfg(not(target_arch = "wasm32"))]
+mod lookup {
+    use std::hint::black_box;
+    use std::io::Cursor;
+
+    use criterion::{BenchmarkId, Criterion, Throughput, criterion_group};
+    use pmtiles::pmtiles::PMTiles;
+    use pmtiles::pmtiles::directory::{CompactDirectory, Directory, DirectoryEntry};
+    use pmtiles::pmtiles::header::Header;
+    use pmtiles::pmtiles::metadata::Metadata;
+    use pmtiles::pmtiles::types::{Compression, TileType};
+    use pmtiles::pmtiles::writer::Writer;
+    use pmtiles::tileid::TileId;
+
+    /// Zoom levels of the synthetic archive; every tile of z0..=8 exists (87,381 tiles).
+    const ARCHIVE_MAX_ZOOM: u8 = 8;
+    /// Entries giving a serialized directory of roughly 16 KiB.
+    const DIRECTORY_ENTRIES: usize = 3000;
+
+    /// Deterministic xorshift so runs are comparable without a rand dependency.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    fn random_tiles(count: usize, max_zoom: u8) -> Vec<(u8, u32, u32)> {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        (0..count).map(|_| {
+            let z = rng.below(max_zoom as u64 + 1) as u8;
+            let n = 1u64 << z;
+            (z, rng.below(n) as u32, rng.below(n) as u32)
+        }).collect()
+    }
+
+    fn tileid(c: &mut Criterion) {
+        let tiles = random_tiles(1024, 16);
+        let ids: Vec<TileId> = tiles.iter().map(|&(z, x, y)| TileId::encode(z, x, y)).collect();
+
+        let mut group = c.benchmark_group("tileid");
+        group.throughput(Throughput::Elements(tiles.len() as u64));
+        group.bench_function("encode", |b| b.iter(|| {
+            for &(z, x, y) in &tiles {
+                black_box(TileId::encode(black_box(z), black_box(x), black_box(y)));
+            }
+        }));
+        group.bench_function("decode", |b| b.iter(|| {
+            for id in &ids {
+                black_box(black_box(id).decode());
+            }
+        }));
+        group.finish();
+    }
+
+    /// z12 tiles in Hilbert order with gaps, short runs and contiguous tile data,
+    /// like the leaf directories of a clustered vector archive.
+    fn realistic_directory() -> Vec<u8> {
+        let mut rng = Rng(42);
+        let mut tile_id = TileId::encode(12, 0, 0).value();
+        let mut offset = 0;
+        let entries = (0..DIRECTORY_ENTRIES).map(|_| {
+            tile_id += 1 + rng.below(4);
+            let length = 200 + rng.below(30_000) as usize;
+            let run_length = if rng.below(10) == 0 { 1 + rng.below(50) as usize } else { 1 };
+            let entry = DirectoryEntry::new(TileId::new(tile_id), offset, length, run_length);
+            tile_id += run_length as u64 - 1;
+            offset += length;
+            entry
+        }).collect();
+        Directory::new(entries).serialize()
+    }
+
+    fn directory_parse(c: &mut Criterion) {
+        let data = realistic_directory();
+        let mut group = c.benchmark_group("directory");
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_with_input(BenchmarkId::new("parse", format!("{} bytes", data.len())), &data, |b, data| {
+            b.iter(|| Directory::parse(black_box(data)).unwrap())
+        });
+        group.bench_with_input(BenchmarkId::new("parse_compact", format!("{} bytes", data.len())), &data, |b, data| {
+            b.iter(|| CompactDirectory::parse(black_box(data)).unwrap())
+        });
+        group.finish();
+    }
+
+    fn synthetic_archive() -> PMTiles {
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        let end = TileId::encode(ARCHIVE_MAX_ZOOM + 1, 0, 0).value();
+        for tile_id in 0..end {
+            writer.add_tile(TileId::new(tile_id), &tile_id.to_le_bytes()).unwrap();
+        }
+        let header = Header {
+            internal_compression: Compression::Gzip,
+            tile_compression: Compression::None,
+            tile_type: TileType::MVT,
+            max_zoom: ARCHIVE_MAX_ZOOM,
+            ..Default::default()
+        };
+        let mut data = Vec::new();
+        writer.finish(&mut data, header, &Metadata::from_json("{}").unwrap()).unwrap();
+        PMTiles::from_bytes(data).unwrap()
+    }
+
+    fn get_tile(c: &mut Criterion) {
+        let pmtiles = synthetic_archive();
+        let tiles = random_tiles(1024, ARCHIVE_MAX_ZOOM);
+
+        let mut group = c.benchmark_group("get_tile");
+        group.throughput(Throughput::Elements(tiles.len() as u64));
+        group.bench_function("random", |b| b.iter(|| {
+            for &(z, x, y) in &tiles {
+                black_box(pmtiles.get_tile(z, x, y).unwrap());
+            }
+        }));
+        group.finish();
+    }
+
+    criterion_group!(benches, tileid, directory_parse, get_tile);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+criterion::criterion_main!(lookup::benches);
+
+#[cfg(target_arch = "wasm32")]
+fn main() {}
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pmtiles::protobufs::{decode_varint, encode_varint};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((value, size)) = decode_varint(data) {
+        assert!(size <= data.len());
+        let mut buffer = Vec::new();
+        encode_varint(value, &mut buffer);
+        // 最短の符号化しか受け付けないので、書き戻すと同じ長さになる
+        assert_eq!(buffer, &data[..size]);
+    }
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pmtiles::pmtiles::directory::Directory;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(directory) = Directory::parse(data) {
+        let reparsed = Directory::parse(&directory.serialize()).expect("serialized directory parses");
+        assert_eq!(reparsed.entries, directory.entries);
+    }
+});
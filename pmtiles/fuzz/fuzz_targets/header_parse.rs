@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pmtiles::pmtiles::header::Header;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = Header::parse(data) {
+        // 読めたヘッダは書き戻しても同じバイト列になる
+        assert_eq!(&header.to_bytes()[..], &data[..127]);
+    }
+});
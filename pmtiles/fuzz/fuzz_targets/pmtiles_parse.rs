@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pmtiles::pmtiles::PMTiles;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(pmtiles) = PMTiles::from_bytes(data.to_vec()) {
+        if let Ok(entries) = pmtiles.tile_entries() {
+            for entry in entries.iter().take(64) {
+                let (z, x, y) = entry.tileid.decode();
+                let _ = pmtiles.get_tile(z, x, y);
+            }
+        }
+    }
+});
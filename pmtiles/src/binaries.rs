@@ -1,3 +1,5 @@
+use std::fmt::Write;
+
 
 #[allow(unused)]
 pub fn print_binary_as_rust_code(bytes: &[u8]) {
@@ -13,19 +15,24 @@ pub fn print_binary_as_rust_code(bytes: &[u8]) {
 
 pub fn print_binary(bytes: &[u8]) {
     println!("Hex Dump:");
-    print!("        ");
+    print!("{}", hex_dump(bytes));
+}
+
+/// Formats `bytes` as the hex dump printed by `print_binary`.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::from("        ");
     for i in 0..16 {
-        print!("{:02x} ", i);
+        let _ = write!(dump, "{:02x} ", i);
     }
-    println!{"\n       ------------------------------------------------"};
+    dump.push_str("\n       ------------------------------------------------\n");
     for (i, byte) in bytes.iter().enumerate() {
         let row = i / 16;
-        match i%16 {
-            0 => print!("{:04x}0 | {:02x} ", row, byte),
-            15 => println!("{:02x}", byte),
-            _ => print!("{:02x} ", byte),
-        }
+        let _ = match i%16 {
+            0 => write!(dump, "{:04x}0 | {:02x} ", row, byte),
+            15 => writeln!(dump, "{:02x}", byte),
+            _ => write!(dump, "{:02x} ", byte),
+        };
     }
-    println!{"\n       ------------------------------------------------"};
+    dump.push_str("\n       ------------------------------------------------\n");
+    dump
 }
-
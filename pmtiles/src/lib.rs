@@ -0,0 +1,7 @@
+pub mod binaries;
+pub mod mvt;
+pub mod pmtiles;
+pub mod protobufs;
+pub mod tileid;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -1,15 +1,14 @@
 use std::io::{self};
 
-mod binaries;
-mod pmtiles;
-mod protobufs;
-mod tileid;
+use pmtiles::pmtiles::PMTiles;
 
 fn main() -> io::Result<()> {
+    env_logger::init();
+
     let file_path = "/mnt/f/GIS/GSI/optimal_bvmap-v1.pmtiles";
     println!("{file_path}");
 
-    let pmtiles = pmtiles::PMTiles::open(file_path)?;
+    let pmtiles = PMTiles::open(file_path)?;
     pmtiles.header.print_info();
     //pmtiles.metadata.print_info();
     pmtiles.root_directory.entries.iter().for_each(|entry| {
@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::protobufs::{VarintReader, encode_varint, zigzag_decode_32, zigzag_decode_64, zigzag_encode_32, zigzag_encode_64};
+
+pub mod clip;
+
+pub const DEFAULT_EXTENT: u32 = 4096;
+
+const WIRE_VARINT: u64 = 0;
+const WIRE_64BIT: u64 = 1;
+const WIRE_LENGTH_DELIMITED: u64 = 2;
+const WIRE_32BIT: u64 = 5;
+
+const COMMAND_MOVE_TO: u32 = 1;
+const COMMAND_LINE_TO: u32 = 2;
+const COMMAND_CLOSE_PATH: u32 = 7;
+
+/// A position in tile coordinates, from 0 to the layer extent (y grows downwards).
+pub type Point = (i32, i32);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GeomType {
+    #[default]
+    Unknown = 0,
+    Point = 1,
+    LineString = 2,
+    Polygon = 3,
+}
+
+impl From<u64> for GeomType {
+    fn from(value: u64) -> Self {
+        match value {
+            1 => GeomType::Point,
+            2 => GeomType::LineString,
+            3 => GeomType::Polygon,
+            _ => GeomType::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Float(f32),
+    Double(f64),
+    Int(i64),
+    Uint(u64),
+    Sint(i64),
+    Bool(bool),
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Feature {
+    pub id: Option<u64>,
+    pub properties: Vec<(String, Value)>,
+    pub geom_type: GeomType,
+    /// Points: one part holding every point. LineString: one part per line.
+    /// Polygon: one part per ring, without repeating the first point; exterior rings
+    /// are clockwise on screen and followed by their holes.
+    pub geometry: Vec<Vec<Point>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub version: u32,
+    pub name: String,
+    pub extent: u32,
+    pub features: Vec<Feature>,
+}
+
+/// A decoded Mapbox Vector Tile. Keys and values are resolved into each feature's
+/// properties; `encode` rebuilds the shared tables.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Tile {
+    pub layers: Vec<Layer>,
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+enum FieldValue<'a> {
+    Varint(u64),
+    Fixed64([u8; 8]),
+    Bytes(&'a [u8]),
+    Fixed32([u8; 4]),
+}
+
+/// Calls `f` with the number and value of every field of a protobuf message.
+fn read_fields<'a>(data: &'a [u8], mut f: impl FnMut(u64, FieldValue<'a>) -> io::Result<()>) -> io::Result<()> {
+    let mut reader = VarintReader::new(data);
+    while !reader.is_empty() {
+        let key = reader.read_varint().map_err(invalid_data)?;
+        let value = match key & 0b111 {
+            WIRE_VARINT => FieldValue::Varint(reader.read_varint().map_err(invalid_data)?),
+            WIRE_64BIT => FieldValue::Fixed64(reader.read_bytes(8).map_err(invalid_data)?.try_into().expect("8 bytes")),
+            WIRE_LENGTH_DELIMITED => {
+                let length = reader.read_varint().map_err(invalid_data)?;
+                let length = usize::try_from(length).map_err(|_| invalid_data("Field is too long"))?;
+                FieldValue::Bytes(reader.read_bytes(length).map_err(invalid_data)?)
+            },
+            WIRE_32BIT => FieldValue::Fixed32(reader.read_bytes(4).map_err(invalid_data)?.try_into().expect("4 bytes")),
+            wire_type => return Err(invalid_data(format!("Unsupported wire type {}", wire_type))),
+        };
+        f(key >> 3, value)?;
+    }
+    Ok(())
+}
+
+/// Reads a repeated uint32 field, packed or not.
+fn read_packed(value: FieldValue, values: &mut Vec<u32>) -> io::Result<()> {
+    let to_u32 = |value: u64| u32::try_from(value).map_err(|_| invalid_data("Value overflows 32 bits"));
+    match value {
+        FieldValue::Varint(value) => values.push(to_u32(value)?),
+        FieldValue::Bytes(data) => {
+            let mut reader = VarintReader::new(data);
+            while !reader.is_empty() {
+                values.push(to_u32(reader.read_varint().map_err(invalid_data)?)?);
+            }
+        },
+        _ => return Err(invalid_data("Unexpected wire type for a packed field")),
+    }
+    Ok(())
+}
+
+fn to_string(data: &[u8]) -> io::Result<String> {
+    String::from_utf8(data.to_vec()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_key(field: u64, wire_type: u64, buffer: &mut Vec<u8>) {
+    encode_varint((field << 3) | wire_type, buffer);
+}
+
+fn write_varint_field(field: u64, value: u64, buffer: &mut Vec<u8>) {
+    write_key(field, WIRE_VARINT, buffer);
+    encode_varint(value, buffer);
+}
+
+fn write_bytes_field(field: u64, data: &[u8], buffer: &mut Vec<u8>) {
+    write_key(field, WIRE_LENGTH_DELIMITED, buffer);
+    encode_varint(data.len() as u64, buffer);
+    buffer.extend_from_slice(data);
+}
+
+fn write_packed_field(field: u64, values: &[u32], buffer: &mut Vec<u8>) {
+    let mut packed = Vec::new();
+    for &value in values {
+        encode_varint(value as u64, &mut packed);
+    }
+    write_bytes_field(field, &packed, buffer);
+}
+
+impl Tile {
+    pub fn decode(data: &[u8]) -> io::Result<Self> {
+        let mut layers = Vec::new();
+        read_fields(data, |field, value| {
+            if let (3, FieldValue::Bytes(data)) = (field, value) {
+                layers.push(Layer::decode(data)?);
+            }
+            Ok(())
+        })?;
+        Ok(Tile { layers })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for layer in &self.layers {
+            write_bytes_field(3, &layer.encode(), &mut buffer);
+        }
+        buffer
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+}
+
+impl Layer {
+    pub fn new(name: &str) -> Self {
+        Layer { version: 2, name: name.to_string(), extent: DEFAULT_EXTENT, features: Vec::new() }
+    }
+
+    pub fn decode(data: &[u8]) -> io::Result<Self> {
+        let mut layer = Layer { version: 1, name: String::new(), extent: DEFAULT_EXTENT, features: Vec::new() };
+        let mut raw_features = Vec::new();
+        let mut keys = Vec::new();
+        let mut values = Vec::new();
+        read_fields(data, |field, value| {
+            match (field, value) {
+                (1, FieldValue::Bytes(data)) => layer.name = to_string(data)?,
+                (2, FieldValue::Bytes(data)) => raw_features.push(data),
+                (3, FieldValue::Bytes(data)) => keys.push(to_string(data)?),
+                (4, FieldValue::Bytes(data)) => values.push(Value::decode(data)?),
+                (5, FieldValue::Varint(extent)) => layer.extent = u32::try_from(extent).map_err(|_| invalid_data("Extent overflows 32 bits"))?,
+                (15, FieldValue::Varint(version)) => layer.version = version as u32,
+                _ => {},
+            }
+            Ok(())
+        })?;
+        if layer.extent == 0 {
+            return Err(invalid_data(format!("Layer {} has an extent of 0", layer.name)));
+        }
+        layer.features = raw_features.into_iter()
+            .map(|data| Feature::decode(data, &keys, &values))
+            .collect::<io::Result<_>>()?;
+        Ok(layer)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        // キーと値はレイヤ内で共有するテーブルにまとめる
+        let mut keys: Vec<&str> = Vec::new();
+        let mut key_indices: HashMap<&str, u32> = HashMap::new();
+        let mut values: Vec<Vec<u8>> = Vec::new();
+        let mut value_indices: HashMap<Vec<u8>, u32> = HashMap::new();
+
+        let mut features = Vec::new();
+        for feature in &self.features {
+            let mut tags = Vec::with_capacity(feature.properties.len() * 2);
+            for (key, value) in &feature.properties {
+                let key_index = *key_indices.entry(key).or_insert_with(|| {
+                    keys.push(key);
+                    keys.len() as u32 - 1
+                });
+                let value = value.encode();
+                let value_index = match value_indices.get(&value) {
+                    Some(&index) => index,
+                    None => {
+                        values.push(value.clone());
+                        value_indices.insert(value, values.len() as u32 - 1);
+                        values.len() as u32 - 1
+                    },
+                };
+                tags.extend([key_index, value_index]);
+            }
+            features.push(feature.encode(&tags));
+        }
+
+        let mut buffer = Vec::new();
+        write_bytes_field(1, self.name.as_bytes(), &mut buffer);
+        for feature in &features {
+            write_bytes_field(2, feature, &mut buffer);
+        }
+        for key in keys {
+            write_bytes_field(3, key.as_bytes(), &mut buffer);
+        }
+        for value in &values {
+            write_bytes_field(4, value, &mut buffer);
+        }
+        write_varint_field(5, self.extent as u64, &mut buffer);
+        write_varint_field(15, self.version as u64, &mut buffer);
+        buffer
+    }
+}
+
+impl Feature {
+    fn decode(data: &[u8], keys: &[String], values: &[Value]) -> io::Result<Self> {
+        let mut feature = Feature::default();
+        let mut tags = Vec::new();
+        let mut commands = Vec::new();
+        read_fields(data, |field, value| {
+            match (field, value) {
+                (1, FieldValue::Varint(id)) => feature.id = Some(id),
+                (2, value) => read_packed(value, &mut tags)?,
+                (3, FieldValue::Varint(geom_type)) => feature.geom_type = GeomType::from(geom_type),
+                (4, value) => read_packed(value, &mut commands)?,
+                _ => {},
+            }
+            Ok(())
+        })?;
+
+        if tags.len() % 2 != 0 {
+            return Err(invalid_data("Feature has an odd number of tags"));
+        }
+        for tag in tags.chunks_exact(2) {
+            let key = keys.get(tag[0] as usize).ok_or_else(|| invalid_data(format!("Key index {} out of range", tag[0])))?;
+            let value = values.get(tag[1] as usize).ok_or_else(|| invalid_data(format!("Value index {} out of range", tag[1])))?;
+            feature.properties.push((key.clone(), value.clone()));
+        }
+        feature.geometry = decode_geometry(&commands)?;
+        Ok(feature)
+    }
+
+    fn encode(&self, tags: &[u32]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        if let Some(id) = self.id {
+            write_varint_field(1, id, &mut buffer);
+        }
+        if !tags.is_empty() {
+            write_packed_field(2, tags, &mut buffer);
+        }
+        write_varint_field(3, self.geom_type as u64, &mut buffer);
+        write_packed_field(4, &encode_geometry(self.geom_type, &self.geometry), &mut buffer);
+        buffer
+    }
+
+    pub fn property(&self, key: &str) -> Option<&Value> {
+        self.properties.iter().find(|(k, _)| k == key).map(|(_, value)| value)
+    }
+}
+
+impl Value {
+    fn decode(data: &[u8]) -> io::Result<Self> {
+        let mut decoded = None;
+        read_fields(data, |field, value| {
+            decoded = Some(match (field, value) {
+                (1, FieldValue::Bytes(data)) => Value::String(to_string(data)?),
+                (2, FieldValue::Fixed32(bytes)) => Value::Float(f32::from_le_bytes(bytes)),
+                (3, FieldValue::Fixed64(bytes)) => Value::Double(f64::from_le_bytes(bytes)),
+                (4, FieldValue::Varint(value)) => Value::Int(value as i64),
+                (5, FieldValue::Varint(value)) => Value::Uint(value),
+                (6, FieldValue::Varint(value)) => Value::Sint(zigzag_decode_64(value)),
+                (7, FieldValue::Varint(value)) => Value::Bool(value != 0),
+                _ => return Ok(()),
+            });
+            Ok(())
+        })?;
+        decoded.ok_or_else(|| invalid_data("Value has no supported field"))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        match self {
+            Value::String(value) => write_bytes_field(1, value.as_bytes(), &mut buffer),
+            Value::Float(value) => {
+                write_key(2, WIRE_32BIT, &mut buffer);
+                buffer.extend(value.to_le_bytes());
+            },
+            Value::Double(value) => {
+                write_key(3, WIRE_64BIT, &mut buffer);
+                buffer.extend(value.to_le_bytes());
+            },
+            Value::Int(value) => write_varint_field(4, *value as u64, &mut buffer),
+            Value::Uint(value) => write_varint_field(5, *value, &mut buffer),
+            Value::Sint(value) => write_varint_field(6, zigzag_encode_64(*value), &mut buffer),
+            Value::Bool(value) => write_varint_field(7, *value as u64, &mut buffer),
+        }
+        buffer
+    }
+
+    /// The value as a number, if it is one.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Float(value) => Some(value as f64),
+            Value::Double(value) => Some(value),
+            Value::Int(value) | Value::Sint(value) => Some(value as f64),
+            Value::Uint(value) => Some(value as f64),
+            Value::String(_) | Value::Bool(_) => None,
+        }
+    }
+}
+
+/// Decodes MoveTo/LineTo/ClosePath commands into parts. Each MoveTo starts a new part.
+fn decode_geometry(commands: &[u32]) -> io::Result<Vec<Vec<Point>>> {
+    let mut parts: Vec<Vec<Point>> = Vec::new();
+    let (mut x, mut y) = (0i32, 0i32);
+    let mut i = 0;
+    while i < commands.len() {
+        let (id, count) = (commands[i] & 0b111, (commands[i] >> 3) as usize);
+        i += 1;
+        match id {
+            COMMAND_MOVE_TO | COMMAND_LINE_TO => {
+                if count > (commands.len() - i) / 2 {
+                    return Err(invalid_data("Geometry command runs past the end"));
+                }
+                if id == COMMAND_LINE_TO && parts.is_empty() {
+                    return Err(invalid_data("LineTo without a preceding MoveTo"));
+                }
+                for j in 0..count {
+                    x = x.wrapping_add(zigzag_decode_32(commands[i]));
+                    y = y.wrapping_add(zigzag_decode_32(commands[i + 1]));
+                    i += 2;
+                    if id == COMMAND_MOVE_TO && j == 0 {
+                        parts.push(Vec::new());
+                    }
+                    parts.last_mut().expect("a part was started").push((x, y));
+                }
+            },
+            COMMAND_CLOSE_PATH => {},
+            _ => return Err(invalid_data(format!("Unknown geometry command {}", id))),
+        }
+    }
+    Ok(parts)
+}
+
+fn command(id: u32, count: usize) -> u32 {
+    id | ((count as u32) << 3)
+}
+
+fn encode_geometry(geom_type: GeomType, parts: &[Vec<Point>]) -> Vec<u32> {
+    let mut commands = Vec::new();
+    let mut cursor = (0i32, 0i32);
+    let mut push_point = |commands: &mut Vec<u32>, (x, y): Point| {
+        commands.push(zigzag_encode_32(x.wrapping_sub(cursor.0)));
+        commands.push(zigzag_encode_32(y.wrapping_sub(cursor.1)));
+        cursor = (x, y);
+    };
+    for part in parts.iter().filter(|part| !part.is_empty()) {
+        if geom_type == GeomType::Point {
+            commands.push(command(COMMAND_MOVE_TO, part.len()));
+            part.iter().for_each(|&point| push_point(&mut commands, point));
+            continue;
+        }
+        commands.push(command(COMMAND_MOVE_TO, 1));
+        push_point(&mut commands, part[0]);
+        if part.len() > 1 {
+            commands.push(command(COMMAND_LINE_TO, part.len() - 1));
+            part[1..].iter().for_each(|&point| push_point(&mut commands, point));
+        }
+        if geom_type == GeomType::Polygon {
+            commands.push(command(COMMAND_CLOSE_PATH, 1));
+        }
+    }
+    commands
+}
+
+/// Twice the signed area of a ring; positive for exterior rings in tile coordinates.
+pub fn ring_area(ring: &[Point]) -> i64 {
+    let mut area = 0i64;
+    for (i, &(x1, y1)) in ring.iter().enumerate() {
+        let (x2, y2) = ring[(i + 1) % ring.len()];
+        area += x1 as i64 * y2 as i64 - x2 as i64 * y1 as i64;
+    }
+    area
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geometry_examples_from_spec() {
+        let point = vec![vec![(25, 17)]];
+        assert_eq!(encode_geometry(GeomType::Point, &point), [9, 50, 34]);
+        assert_eq!(decode_geometry(&[9, 50, 34]).unwrap(), point);
+
+        let line = vec![vec![(2, 2), (2, 10), (10, 10)]];
+        assert_eq!(encode_geometry(GeomType::LineString, &line), [9, 4, 4, 18, 0, 16, 16, 0]);
+        assert_eq!(decode_geometry(&[9, 4, 4, 18, 0, 16, 16, 0]).unwrap(), line);
+
+        let polygon = vec![vec![(3, 6), (8, 12), (20, 34)]];
+        assert_eq!(encode_geometry(GeomType::Polygon, &polygon), [9, 6, 12, 18, 10, 12, 24, 44, 15]);
+        assert_eq!(decode_geometry(&[9, 6, 12, 18, 10, 12, 24, 44, 15]).unwrap(), polygon);
+
+        assert!(decode_geometry(&[9, 50]).is_err());
+        assert!(decode_geometry(&[18, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn tile_round_trip() {
+        let mut roads = Layer::new("road");
+        roads.features.push(Feature {
+            id: Some(1),
+            properties: vec![("name".to_string(), Value::String("国道1号".to_string())), ("lanes".to_string(), Value::Uint(4))],
+            geom_type: GeomType::LineString,
+            geometry: vec![vec![(0, 0), (100, 100)], vec![(200, 0), (200, 4096)]],
+        });
+        roads.features.push(Feature {
+            id: None,
+            properties: vec![("name".to_string(), Value::String("県道".to_string())), ("oneway".to_string(), Value::Bool(true))],
+            geom_type: GeomType::LineString,
+            geometry: vec![vec![(-10, 5), (20, 5)]],
+        });
+        let mut buildings = Layer::new("building");
+        buildings.extent = 512;
+        buildings.features.push(Feature {
+            id: Some(7),
+            properties: vec![
+                ("height".to_string(), Value::Double(12.5)),
+                ("ratio".to_string(), Value::Float(0.25)),
+                ("level".to_string(), Value::Int(-1)),
+                ("floor".to_string(), Value::Sint(-2)),
+            ],
+            geom_type: GeomType::Polygon,
+            geometry: vec![vec![(0, 0), (10, 0), (10, 10), (0, 10)], vec![(2, 2), (2, 8), (8, 8), (8, 2)]],
+        });
+        let mut pois = Layer::new("poi");
+        pois.features.push(Feature { geom_type: GeomType::Point, geometry: vec![vec![(1, 2), (3, 4)]], ..Default::default() });
+        let tile = Tile { layers: vec![roads, buildings, pois] };
+
+        let data = tile.encode();
+        let decoded = Tile::decode(&data).unwrap();
+        assert_eq!(decoded, tile);
+        assert_eq!(decoded.layer("road").unwrap().features[1].property("oneway"), Some(&Value::Bool(true)));
+        assert!(ring_area(&decoded.layer("building").unwrap().features[0].geometry[0]) > 0);
+        assert!(Tile::decode(&data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn keys_and_values_are_shared() {
+        let feature = Feature {
+            properties: vec![("kind".to_string(), Value::String("park".to_string()))],
+            geom_type: GeomType::Point,
+            geometry: vec![vec![(1, 1)]],
+            ..Default::default()
+        };
+        let mut layer = Layer::new("landuse");
+        layer.features = vec![feature.clone(), feature];
+
+        let mut counts = [0; 5];
+        read_fields(&layer.encode(), |field, _| {
+            if let Some(count) = counts.get_mut(field as usize) {
+                *count += 1;
+            }
+            Ok(())
+        }).unwrap();
+        // name, features x2, key x1, value x1
+        assert_eq!(counts[1..], [1, 2, 1, 1]);
+    }
+}
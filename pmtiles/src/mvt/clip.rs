@@ -0,0 +1,238 @@
+use super::{Feature, GeomType, Layer, Point, Tile, ring_area};
+
+/// Margin kept around a clipped tile, as a fraction of the extent (64 units at 4096),
+/// so that strokes and polygon edges do not end exactly at the tile border.
+const BUFFER: f64 = 64.0 / 4096.0;
+
+type Position = (f64, f64);
+
+/// The rectangle geometry is clipped to, in child tile coordinates.
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    min: f64,
+    max: f64,
+}
+
+impl Bounds {
+    fn contains(&self, (x, y): Position) -> bool {
+        self.min <= x && x <= self.max && self.min <= y && y <= self.max
+    }
+}
+
+/// Synthesizes a tile `dz` zoom levels below `tile`: the geometry of the child at
+/// (`dx`, `dy`) within the parent (each from 0 to 2^dz - 1) is clipped and rescaled to the
+/// full extent. Features and layers left without geometry are dropped.
+pub fn overzoom(tile: &Tile, dz: u8, dx: u32, dy: u32) -> Tile {
+    let scale = 2f64.powi(dz as i32);
+    let layers = tile.layers.iter().filter_map(|layer| {
+        let extent = layer.extent as f64;
+        let bounds = Bounds { min: -extent * BUFFER, max: extent * (1.0 + BUFFER) };
+        let transform = |&(x, y): &Point| (x as f64 * scale - dx as f64 * extent, y as f64 * scale - dy as f64 * extent);
+
+        let features: Vec<Feature> = layer.features.iter().filter_map(|feature| {
+            let parts: Vec<Vec<Position>> = feature.geometry.iter()
+                .map(|part| part.iter().map(transform).collect())
+                .collect();
+            let geometry = match feature.geom_type {
+                GeomType::Point => clip_points(&parts, bounds),
+                GeomType::LineString => parts.iter().flat_map(|line| clip_line(line, bounds)).collect(),
+                GeomType::Polygon => clip_polygon(&parts, bounds),
+                GeomType::Unknown => Vec::new(),
+            };
+            (!geometry.is_empty()).then(|| Feature { geometry, ..feature.clone() })
+        }).collect();
+
+        (!features.is_empty()).then(|| Layer { features, ..layer.clone() })
+    }).collect();
+    Tile { layers }
+}
+
+fn round(position: Position) -> Point {
+    (position.0.round() as i32, position.1.round() as i32)
+}
+
+/// Rounds to tile coordinates, dropping points that become duplicates of the previous one.
+fn round_part(part: &[Position]) -> Vec<Point> {
+    let mut rounded: Vec<Point> = part.iter().copied().map(round).collect();
+    rounded.dedup();
+    rounded
+}
+
+fn clip_points(parts: &[Vec<Position>], bounds: Bounds) -> Vec<Vec<Point>> {
+    let points: Vec<Point> = parts.iter().flatten().copied()
+        .filter(|&position| bounds.contains(position))
+        .map(round)
+        .collect();
+    if points.is_empty() { Vec::new() } else { vec![points] }
+}
+
+/// Liang–Barsky: the part of segment a-b inside the bounds, if any.
+fn clip_segment(a: Position, b: Position, bounds: Bounds) -> Option<(Position, Position)> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (0.0f64, 1.0f64);
+    let edges = [(-dx, a.0 - bounds.min), (dx, bounds.max - a.0), (-dy, a.1 - bounds.min), (dy, bounds.max - a.1)];
+    for (p, q) in edges {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    let at = |t: f64| if t == 0.0 { a } else if t == 1.0 { b } else { (a.0 + t * dx, a.1 + t * dy) };
+    Some((at(t0), at(t1)))
+}
+
+/// Clips a line, splitting it wherever it leaves the bounds.
+fn clip_line(line: &[Position], bounds: Bounds) -> Vec<Vec<Point>> {
+    let mut lines = Vec::new();
+    let mut current: Vec<Position> = Vec::new();
+    let mut flush = |current: &mut Vec<Position>| {
+        let line = round_part(current);
+        if line.len() >= 2 {
+            lines.push(line);
+        }
+        current.clear();
+    };
+    for segment in line.windows(2) {
+        match clip_segment(segment[0], segment[1], bounds) {
+            Some((start, end)) => {
+                if current.last() != Some(&start) {
+                    flush(&mut current);
+                    current.push(start);
+                }
+                current.push(end);
+                // 線分の途中で範囲外に出たら、そこで線を切る
+                if end != segment[1] {
+                    flush(&mut current);
+                }
+            },
+            None => flush(&mut current),
+        }
+    }
+    flush(&mut current);
+    lines
+}
+
+/// Sutherland–Hodgman against each side of the bounds. Orientation is preserved.
+fn clip_ring(ring: &[Position], bounds: Bounds) -> Vec<Position> {
+    let mut ring = ring.to_vec();
+    for (axis, edge, keep_above) in [(0, bounds.min, true), (0, bounds.max, false), (1, bounds.min, true), (1, bounds.max, false)] {
+        ring = clip_ring_side(&ring, axis, edge, keep_above);
+    }
+    ring
+}
+
+/// Keeps the part of a ring on one side of the line x = `edge` (axis 0) or y = `edge` (axis 1).
+fn clip_ring_side(ring: &[Position], axis: usize, edge: f64, keep_above: bool) -> Vec<Position> {
+    let value = |p: Position| if axis == 0 { p.0 } else { p.1 };
+    let inside = |p: Position| if keep_above { value(p) >= edge } else { value(p) <= edge };
+    let intersect = |a: Position, b: Position| {
+        let t = (edge - value(a)) / (value(b) - value(a));
+        if axis == 0 { (edge, a.1 + t * (b.1 - a.1)) } else { (a.0 + t * (b.0 - a.0), edge) }
+    };
+
+    let mut output = Vec::new();
+    for (i, &current) in ring.iter().enumerate() {
+        let previous = ring[(i + ring.len() - 1) % ring.len()];
+        match (inside(previous), inside(current)) {
+            (true, true) => output.push(current),
+            (true, false) => output.push(intersect(previous, current)),
+            (false, true) => {
+                output.push(intersect(previous, current));
+                output.push(current);
+            },
+            (false, false) => {},
+        }
+    }
+    output
+}
+
+/// Clips every ring. Holes are dropped together with their exterior ring.
+fn clip_polygon(rings: &[Vec<Position>], bounds: Bounds) -> Vec<Vec<Point>> {
+    let mut clipped = Vec::new();
+    let mut keep_holes = false;
+    for ring in rings {
+        let is_exterior = ring_area(&round_part(ring)) > 0;
+        if !is_exterior && !keep_holes {
+            continue;
+        }
+        let mut ring = round_part(&clip_ring(ring, bounds));
+        if ring.len() > 1 && ring.first() == ring.last() {
+            ring.pop();
+        }
+        let valid = ring.len() >= 3 && ring_area(&ring) != 0;
+        if is_exterior {
+            keep_holes = valid;
+        }
+        if valid {
+            clipped.push(ring);
+        }
+    }
+    clipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer_with(geom_type: GeomType, geometry: Vec<Vec<Point>>) -> Tile {
+        let mut layer = Layer::new("test");
+        layer.features.push(Feature { geom_type, geometry, ..Default::default() });
+        Tile { layers: vec![layer] }
+    }
+
+    fn geometry(tile: &Tile) -> &Vec<Vec<Point>> {
+        &tile.layers[0].features[0].geometry
+    }
+
+    #[test]
+    fn overzoom_points() {
+        let tile = layer_with(GeomType::Point, vec![vec![(100, 100), (3000, 100), (2000, 2000)]]);
+        // 左上の子タイル
+        let child = overzoom(&tile, 1, 0, 0);
+        assert_eq!(geometry(&child), &vec![vec![(200, 200), (4000, 4000)]]);
+        assert!(overzoom(&tile, 1, 0, 1).layers.is_empty());
+    }
+
+    #[test]
+    fn overzoom_lines() {
+        // 左上の子タイルを出て、また戻ってくる線
+        let tile = layer_with(GeomType::LineString, vec![vec![(1000, 1000), (3000, 1000), (3000, 1500), (1000, 1500)]]);
+        let child = overzoom(&tile, 1, 0, 0);
+        assert_eq!(geometry(&child), &vec![vec![(2000, 2000), (4160, 2000)], vec![(4160, 3000), (2000, 3000)]]);
+
+        let child = overzoom(&tile, 1, 1, 0);
+        assert_eq!(geometry(&child), &vec![vec![(-64, 2000), (1904, 2000), (1904, 3000), (-64, 3000)]]);
+    }
+
+    #[test]
+    fn overzoom_polygons() {
+        // 全体を覆う外周と、左上の子タイルだけにある穴
+        let exterior = vec![(0, 0), (4096, 0), (4096, 4096), (0, 4096)];
+        let hole = vec![(100, 100), (100, 200), (200, 200), (200, 100)];
+        assert!(ring_area(&exterior) > 0 && ring_area(&hole) < 0);
+        let tile = layer_with(GeomType::Polygon, vec![exterior, hole]);
+
+        let child = overzoom(&tile, 1, 0, 0);
+        assert_eq!(geometry(&child), &vec![
+            vec![(0, 4160), (0, 0), (4160, 0), (4160, 4160)],
+            vec![(200, 200), (200, 400), (400, 400), (400, 200)],
+        ]);
+        let child = overzoom(&tile, 2, 3, 3);
+        assert_eq!(geometry(&child), &vec![vec![(-64, -64), (4096, -64), (4096, 4096), (-64, 4096)]]);
+
+        // 外周が消えると穴も消える
+        let tile = layer_with(GeomType::Polygon, vec![vec![(0, 0), (100, 0), (100, 100), (0, 100)], vec![(10, 10), (10, 20), (20, 20), (20, 10)]]);
+        assert!(overzoom(&tile, 1, 1, 1).layers.is_empty());
+    }
+}
@@ -1,49 +1,126 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io;
-use memmap2::Mmap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::{Deref, Range};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
-mod directory;
-mod header;
-mod metadata;
-mod types;
+pub mod cluster;
+pub mod compression;
+pub mod coverage;
+pub mod directory;
+pub mod edit;
+pub mod filter;
+pub mod hash;
+pub mod header;
+pub mod inspect;
+pub mod metadata;
+#[cfg(feature = "raster")]
+pub mod overview;
+pub mod overzoom;
+pub mod query;
+#[cfg(feature = "raster")]
+pub mod raster;
+pub mod scan;
+pub mod tilejson;
+#[cfg(test)]
+mod testing;
+pub mod tilestats;
+pub mod transcode;
+pub mod types;
+pub mod v2;
+pub mod writer;
 
-use directory::Directory;
+use directory::{CompactDirectory, Directory, DirectoryEntry};
 use metadata::Metadata;
-use header::Header;
-use crate::{binaries::print_binary, tileid::TileId};
+use header::{HEADER_SIZE, Header};
+use v2::PMTilesV2;
+use crate::{binaries::{hex_dump, print_binary}, tileid::TileId};
+
+const MAX_DIRECTORY_DEPTH: usize = 4;
+/// Leaf directories kept by `get_tile`; lookups near each other keep hitting the same few leaves.
+const LEAF_CACHE_SIZE: usize = 64;
+
+/// The bytes of an archive: a memory-mapped file or any owned buffer.
+struct Data(Box<dyn AsRef<[u8]> + Send + Sync>);
+
+impl Deref for Data {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.0.as_ref().as_ref()
+    }
+}
+
+impl Data {
+    /// Maps the file into memory, or reads it whole when built without the `mmap` feature.
+    fn open(file_path: &str) -> io::Result<Self> {
+        #[cfg(feature = "mmap")]
+        {
+            let f = File::open(file_path)?;
+            let mmap = unsafe { memmap2::Mmap::map(&f)? };
+            Ok(Data(Box::new(mmap)))
+        }
+        #[cfg(not(feature = "mmap"))]
+        Ok(Data(Box::new(std::fs::read(file_path)?)))
+    }
+}
+
+impl fmt::Debug for Data {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Data({} bytes)", self.len())
+    }
+}
 
 #[allow(unused)]
 #[derive(Debug)]
 pub struct PMTiles {
-    data: Mmap,
+    data: Data,
     pub header: Header,
     pub root_directory: Directory,
     pub metadata: Metadata,
+    /// Leaves parsed by `get_tile`, by offset in the leaf directories section.
+    leaf_cache: Mutex<HashMap<usize, Arc<CompactDirectory>>>,
 }
 
 #[allow(unused)]
 impl PMTiles {
     pub fn open(file_path: &str) -> io::Result<Self> {
-        let f = File::open(file_path)?;
-        let mmap = unsafe { Mmap::map(&f)? };
-        let pmtiles = PMTiles::parse(mmap)?;
-        Ok(pmtiles)
+        PMTiles::parse(Data::open(file_path)?)
+    }
+
+    /// Reads an archive held in memory, e.g. a `Vec<u8>` or an `include_bytes!` slice.
+    pub fn from_bytes(data: impl AsRef<[u8]> + Send + Sync + 'static) -> io::Result<Self> {
+        PMTiles::parse(Data(Box::new(data)))
+    }
+
+    /// Reads a whole archive from the start of `reader` into memory.
+    pub fn from_reader<R: Read + Seek>(mut reader: R) -> io::Result<Self> {
+        let mut data = Vec::new();
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_to_end(&mut data)?;
+        PMTiles::from_bytes(data)
     }
 
-    fn parse(data: Mmap) -> io::Result<Self> {
-        let header = &data[..127];
-        print_binary(&header);
-        //print_binary_as_rust_code(&header);
+    fn parse(data: Data) -> io::Result<Self> {
+        let header = &data[..data.len().min(HEADER_SIZE)];
+        // ヘッダのダンプはRUST_LOG=traceのときだけ
+        if log::log_enabled!(log::Level::Trace) {
+            log::trace!("Header:\n{}", hex_dump(header));
+        }
 
-        let header = Header::parse(&header)?;
+        let header = Header::parse(header)?;
+        for warning in header.validate(data.len())? {
+            log::warn!("{}", warning);
+        }
 
-        let compressed_root_dir = &data[header.root_dir_offset .. (header.root_dir_offset+header.root_dir_length)];
-        let root_dir = Directory::parse_compressed(&compressed_root_dir)?;
+        let compressed_root_dir = slice(&data, header.root_dir_offset, header.root_dir_length)?;
+        let root_dir = Directory::parse_compressed(compressed_root_dir, header.internal_compression)?;
 
-        let compressed_metadata = &data[header.metadata_offset .. (header.metadata_offset+header.metadata_length)];
-        let metadata = Metadata::parse_compressed(&compressed_metadata, header.tile_type)?;
+        let compressed_metadata = slice(&data, header.metadata_offset, header.metadata_length)?;
+        let metadata = Metadata::parse_compressed(compressed_metadata, header.internal_compression, header.tile_type)?;
 
-        Ok(PMTiles {data: data, header: header, root_directory: root_dir, metadata: metadata} )
+        Ok(PMTiles {data, header, root_directory: root_dir, metadata, leaf_cache: Mutex::default()} )
     }
 
     pub fn print_info(&self) {
@@ -54,63 +131,253 @@ impl PMTiles {
         self.metadata.print_info();
     }
 
-    pub fn get(&self, z: u8, x: u32, y: u32)  {
+    /// Looks up a tile, following leaf directories, and returns its bytes as stored
+    /// (still compressed with `header.tile_compression`).
+    pub fn get_tile(&self, z: u8, x: u32, y: u32) -> io::Result<Option<&[u8]>> {
         let tile_id = TileId::encode(z, x, y);
 
-        println!("Get Tile z:{}, x:{}, y:{}, tile_id:{}", z, x, y, tile_id.value());
+        let mut entry = self.root_directory.find_entry(tile_id).cloned();
+        // ルート + リーフの階層は仕様上最大4段まで
+        for _ in 0..MAX_DIRECTORY_DEPTH {
+            match entry {
+                None => return Ok(None),
+                Some(entry) if entry.run_length > 0 => return self.tile_data(&entry).map(Some),
+                Some(leaf_entry) => entry = self.cached_leaf(&leaf_entry)?.find_entry(tile_id),
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, "Leaf directories are nested too deeply"))
+    }
 
-        let mut smallest_tile_id = TileId::new(0);
-        let mut smallest_entry: Option<&directory::DirectoryEntry> = None;
-        self.root_directory.entries.iter().for_each(|entry| {
-            if entry.tileid.value() == tile_id.value() {
-                println!("Found Tile in Directory Entry: {}", entry);
-                if entry.run_length == 0 {
-                    println!("Note: This entry has a run length of {}, meaning it covers multiple tiles starting from TileID {}", entry.run_length, entry.tileid.value());
-                } else {
-                    let tile_data_offset = entry.offset;
-                    let tile_data_length = entry.length;
-                    let tile_data = &self.data[tile_data_offset .. (tile_data_offset + tile_data_length)];
-                    print_binary(&tile_data);
+    /// Returns the stored bytes of a tile entry.
+    pub fn tile_data(&self, entry: &DirectoryEntry) -> io::Result<&[u8]> {
+        self.slice(self.header.tile_data_offset.saturating_add(entry.offset), entry.length)
+    }
+
+    /// Calls `f` for each of `entries` with `transform` applied to its tile data. Entries
+    /// sharing an (offset, length) are transformed only once, wherever they appear, and the
+    /// result is kept only until the last entry that needs it.
+    pub fn for_each_unique_tile<T, F, G>(&self, entries: &[DirectoryEntry], mut transform: F, mut f: G) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) -> io::Result<T>,
+        G: FnMut(&DirectoryEntry, &T) -> io::Result<()>,
+    {
+        let mut remaining: HashMap<(usize, usize), usize> = HashMap::new();
+        for entry in entries {
+            *remaining.entry((entry.offset, entry.length)).or_default() += 1;
+        }
+        let mut cache: HashMap<(usize, usize), T> = HashMap::new();
+        for entry in entries {
+            let key = (entry.offset, entry.length);
+            let value = match cache.remove(&key) {
+                Some(value) => value,
+                None => transform(self.tile_data(entry)?)?,
+            };
+            f(entry, &value)?;
+            if let Some(count) = remaining.get_mut(&key) {
+                *count -= 1;
+                if *count > 0 {
+                    cache.insert(key, value);
                 }
-                return;
-            } else if (tile_id.value() < entry.tileid.value()) {
-            } else if (tile_id.value() > entry.tileid.value()) {
-                smallest_tile_id = entry.tileid;
-                smallest_entry = Some(entry);
-                println!("smallest_tile_id: {}", smallest_tile_id.value());
             }
-        });
-        match smallest_entry {
-            Some(entry) => {
-                if (entry.run_length) >= 1 {
-                    let tile_data_offset = entry.offset;
-                    let tile_data_length = entry.length;
-                    let tile_data = &self.data[tile_data_offset .. (tile_data_offset + tile_data_length)];
-                    print_binary(&tile_data);
-                } else {
-                    println!("leaf dirs offset: {}, entry offset: {}, length: {}", self.header.leaf_dirs_offset, entry.offset, entry.length);
-                    let offset = self.header.leaf_dirs_offset + entry.offset;
-                    let leaf_data = &self.data[offset .. (offset + entry.length)];
-                    let  a = Directory::parse_compressed(leaf_data).expect("leaf directory");
-                    a.entries.iter().for_each(|leaf_entry| {
-                        println!("Leaf Directory Entry: {}", leaf_entry);
-                        if leaf_entry.tileid.value() == tile_id.value() {
-                            println!("Found Tile in Leaf Directory Entry: {}", leaf_entry);
-                            let tile_data_offset = leaf_entry.offset;
-                            let tile_data_length = leaf_entry.length;
-                            let tile_data = &self.data[tile_data_offset .. (tile_data_offset + tile_data_length)];
-                            print_binary(&tile_data);
-                        }
-                    });
+        }
+        Ok(())
+    }
 
+    /// Reads the leaf directory a run_length 0 entry points to.
+    pub fn read_leaf(&self, entry: &DirectoryEntry) -> io::Result<Directory> {
+        let data = self.slice(self.header.leaf_dirs_offset.saturating_add(entry.offset), entry.length)?;
+        Directory::parse_compressed(data, self.header.internal_compression)
+    }
+
+    /// Like `read_leaf`, but keeps the entries in the compact form used by `get_tile`.
+    pub fn read_compact_leaf(&self, entry: &DirectoryEntry) -> io::Result<CompactDirectory> {
+        let data = self.slice(self.header.leaf_dirs_offset.saturating_add(entry.offset), entry.length)?;
+        CompactDirectory::parse_compressed(data, self.header.internal_compression)
+    }
+
+    fn leaf_cache(&self) -> MutexGuard<'_, HashMap<usize, Arc<CompactDirectory>>> {
+        // 中身はただのキャッシュなので、毒されていてもそのまま使える
+        self.leaf_cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn cached_leaf(&self, entry: &DirectoryEntry) -> io::Result<Arc<CompactDirectory>> {
+        if let Some(leaf) = self.leaf_cache().get(&entry.offset) {
+            return Ok(Arc::clone(leaf));
+        }
+        // 展開とパースはロックの外で行う
+        let leaf = Arc::new(self.read_compact_leaf(entry)?);
+        let mut leaves = self.leaf_cache();
+        if leaves.len() >= LEAF_CACHE_SIZE {
+            leaves.clear();
+        }
+        leaves.insert(entry.offset, Arc::clone(&leaf));
+        Ok(leaf)
+    }
+
+    /// Collects the tile entries of the root and every leaf directory, in TileID order.
+    pub fn tile_entries(&self) -> io::Result<Vec<DirectoryEntry>> {
+        self.tile_entries_in(0..u64::MAX)
+    }
+
+    /// Like `tile_entries`, but only the entries whose runs overlap `range`, reading only
+    /// the leaves that may hold them.
+    pub fn tile_entries_in(&self, range: Range<u64>) -> io::Result<Vec<DirectoryEntry>> {
+        let mut entries = Vec::new();
+        self.collect_tile_entries(&self.root_directory, &range, 1, &mut entries)?;
+        Ok(entries)
+    }
+
+    fn collect_tile_entries(&self, directory: &Directory, range: &Range<u64>, depth: usize, entries: &mut Vec<DirectoryEntry>) -> io::Result<()> {
+        if depth > MAX_DIRECTORY_DEPTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Leaf directories are nested too deeply"));
+        }
+        for (i, entry) in directory.entries.iter().enumerate() {
+            let start = entry.tileid.value();
+            if start >= range.end {
+                break;
+            }
+            if entry.run_length > 0 {
+                if start.saturating_add(entry.run_length as u64) > range.start {
+                    entries.push(entry.clone());
+                }
+            } else {
+                // リーフが持つTileIDは次のエントリの手前まで
+                let leaf_end = directory.entries.get(i + 1).map_or(u64::MAX, |next| next.tileid.value());
+                if leaf_end > range.start {
+                    self.collect_tile_entries(&self.read_leaf(entry)?, range, depth + 1, entries)?;
                 }
-            },
-            None => {
-                println!("Tile not found and no higher TileID exists in the directory.");
             }
         }
+        Ok(())
+    }
 
+    pub fn get(&self, z: u8, x: u32, y: u32)  {
+        let tile_id = TileId::encode(z, x, y);
+
+        println!("Get Tile z:{}, x:{}, y:{}, tile_id:{}", z, x, y, tile_id.value());
+
+        match self.get_tile(z, x, y) {
+            Ok(Some(tile_data)) => print_binary(tile_data),
+            Ok(None) => println!("Tile not found."),
+            Err(e) => println!("Failed to read tile: {}", e),
+        }
     }
 
+    fn slice(&self, offset: usize, length: usize) -> io::Result<&[u8]> {
+        slice(&self.data, offset, length)
+    }
+}
+
+fn slice(data: &[u8], offset: usize, length: usize) -> io::Result<&[u8]> {
+    offset.checked_add(length)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Range {}+{} is outside of the archive ({} bytes)", offset, length, data.len())
+        ))
+}
+
+/// A PMTiles archive of either supported version, behind the same tile lookup API.
+#[derive(Debug)]
+pub enum Archive {
+    V2(PMTilesV2),
+    V3(PMTiles),
 }
 
+impl Archive {
+    pub fn open(file_path: &str) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        File::open(file_path)?.read_exact(&mut magic)?;
+        if v2::is_v2(&magic) {
+            Ok(Archive::V2(PMTilesV2::open(file_path)?))
+        } else {
+            Ok(Archive::V3(PMTiles::open(file_path)?))
+        }
+    }
+
+    pub fn get_tile(&self, z: u8, x: u32, y: u32) -> io::Result<Option<&[u8]>> {
+        match self {
+            Archive::V2(pmtiles) => pmtiles.get_tile(z, x, y),
+            Archive::V3(pmtiles) => pmtiles.get_tile(z, x, y),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use header::Header;
+    use types::{Compression, TileType};
+
+    #[test]
+    fn open_from_bytes_and_reader() {
+        let tiles = (0..5u64).map(|i| (TileId::new(i), i.to_le_bytes()));
+        let data = testing::archive_bytes(testing::test_header(TileType::MVT, Compression::None), r#"{"name":"test"}"#, tiles);
+
+        let from_bytes = PMTiles::from_bytes(data.clone()).unwrap();
+        assert_eq!(from_bytes.get_tile(1, 1, 1).unwrap(), Some(&3u64.to_le_bytes()[..]));
+        assert_eq!(from_bytes.metadata, Metadata::from_json(r#"{"name":"test"}"#).unwrap());
+
+        let mut reader = Cursor::new(data.clone());
+        reader.set_position(42);
+        let from_reader = PMTiles::from_reader(reader).unwrap();
+        assert_eq!(from_reader.tile_entries().unwrap(), from_bytes.tile_entries().unwrap());
+
+        // 'static なスライスも受け付ける
+        let leaked: &'static [u8] = data.leak();
+        assert!(PMTiles::from_bytes(leaked).unwrap().get_tile(0, 0, 0).unwrap().is_some());
+        assert_eq!(PMTiles::from_bytes(vec![0u8; 10]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn transform_interleaved_duplicates_once() {
+        let mut writer = writer::Writer::new(Cursor::new(Vec::new()));
+        for (i, data) in [b"a", b"b", b"a", b"b", b"a"].iter().enumerate() {
+            writer.add_tile(TileId::new(i as u64), *data).unwrap();
+        }
+        let header = Header { internal_compression: Compression::Gzip, max_zoom: 1, ..Default::default() };
+        let mut data = Vec::new();
+        writer.finish(&mut data, header, &Metadata::from_json("{}").unwrap()).unwrap();
+        let pmtiles = PMTiles::from_bytes(data).unwrap();
+        let entries = pmtiles.tile_entries().unwrap();
+        assert_eq!(entries.len(), 5);
+
+        let mut transformed = 0;
+        let mut tiles = Vec::new();
+        pmtiles.for_each_unique_tile(&entries, |data| {
+            transformed += 1;
+            Ok(data.to_ascii_uppercase())
+        }, |entry, data| {
+            tiles.push((entry.tileid.value(), data.clone()));
+            Ok(())
+        }).unwrap();
+        assert_eq!(transformed, 2);
+        assert_eq!(tiles, [(0, b"A".to_vec()), (1, b"B".to_vec()), (2, b"A".to_vec()), (3, b"B".to_vec()), (4, b"A".to_vec())]);
+    }
+
+    #[test]
+    fn get_tile_through_cached_leaves() {
+        let tiles = (0..20_000u64).map(|i| (TileId::new(i), i.to_le_bytes()));
+        let pmtiles = testing::open_archive(testing::test_header(TileType::MVT, Compression::None), "{}", tiles);
+        assert!(pmtiles.root_directory.entries.iter().all(|entry| entry.run_length == 0));
+        // 2回目はキャッシュしたリーフから引く
+        for _ in 0..2 {
+            for i in (0..20_000u64).step_by(97) {
+                let (z, x, y) = TileId::new(i).decode();
+                assert_eq!(pmtiles.get_tile(z, x, y).unwrap(), Some(&i.to_le_bytes()[..]));
+            }
+        }
+        assert_eq!(pmtiles.leaf_cache().len(), pmtiles.root_directory.entries.len());
+    }
+
+    #[test]
+    fn tile_entries_in_range() {
+        let tiles = (0..20_000u64).map(|i| (TileId::new(i), i.to_le_bytes()));
+        let pmtiles = testing::open_archive(testing::test_header(TileType::MVT, Compression::None), "{}", tiles);
+        assert_eq!(pmtiles.tile_entries().unwrap().len(), 20_000);
+        let entries = pmtiles.tile_entries_in(12_345..12_400).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.tileid.value()).collect::<Vec<_>>(), (12_345..12_400).collect::<Vec<_>>());
+        assert!(pmtiles.tile_entries_in(20_000..u64::MAX).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,89 @@
+use std::io;
+
+use super::PMTiles;
+use super::header::Header;
+use super::writer::write_to_file;
+use crate::tileid::TileId;
+
+/// Rewrites `input_path` so that tile data is ordered by TileID.
+///
+/// Contents shared by several tiles are stored once, at the position of the first tile
+/// using them, and the directories use the zero offset encoding for contiguous tiles.
+/// Tile bytes are copied as they are, without recompression.
+pub fn cluster(input_path: &str, output_path: &str) -> io::Result<Header> {
+    let input = PMTiles::open(input_path)?;
+    // ディレクトリのエントリはTileID順に並んでいる
+    let entries = input.tile_entries()?;
+
+    write_to_file(output_path, input.header.clone(), &input.metadata, |writer| {
+        for entry in &entries {
+            let data = input.tile_data(entry)?;
+            for i in 0..entry.run_length as u64 {
+                writer.add_tile(TileId::new(entry.tileid.value() + i), data)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::pmtiles::directory::{Directory, DirectoryEntry};
+    use crate::pmtiles::header::HEADER_SIZE;
+    use crate::pmtiles::metadata::Metadata;
+    use crate::pmtiles::testing::{TempPath, test_header};
+    use crate::pmtiles::types::{Compression, TileType};
+
+    /// Tile data stored in reverse TileID order, with TileID 3 reusing TileID 0's bytes.
+    /// `Writer` always clusters, so this archive is laid out by hand.
+    fn unclustered_archive() -> Vec<u8> {
+        let tile_data = b"CCCBBAAAA";
+        let directory = Directory::new(vec![
+            DirectoryEntry::new(TileId::new(0), 5, 4, 1),
+            DirectoryEntry::new(TileId::new(1), 3, 2, 1),
+            DirectoryEntry::new(TileId::new(2), 0, 3, 1),
+            DirectoryEntry::new(TileId::new(3), 5, 4, 1),
+        ]);
+        let root_dir = directory.serialize_compressed(Compression::Gzip).unwrap();
+        let metadata = Metadata::from_json("{}").unwrap().to_compressed(Compression::Gzip).unwrap();
+
+        let header = Header {
+            version: 3,
+            root_dir_offset: HEADER_SIZE,
+            root_dir_length: root_dir.len(),
+            metadata_offset: HEADER_SIZE + root_dir.len(),
+            metadata_length: metadata.len(),
+            leaf_dirs_offset: HEADER_SIZE + root_dir.len() + metadata.len(),
+            tile_data_offset: HEADER_SIZE + root_dir.len() + metadata.len(),
+            tile_data_length: tile_data.len(),
+            num_addressed_tiles: 4,
+            num_tile_entries: 4,
+            num_tile_contents: 3,
+            clustered: 0,
+            ..test_header(TileType::MVT, Compression::None)
+        };
+        [&header.to_bytes()[..], &root_dir, &metadata, tile_data].concat()
+    }
+
+    #[test]
+    fn cluster_reorders_tile_data() {
+        let (input, output) = (TempPath::new("unclustered"), TempPath::new("clustered"));
+        fs::write(&input, unclustered_archive()).unwrap();
+
+        let header = cluster(input.as_str(), output.as_str()).unwrap();
+        let clustered = PMTiles::open(output.as_str()).unwrap();
+        let entries = clustered.tile_entries().unwrap();
+        let start = clustered.header.tile_data_offset;
+        let tile_data = clustered.data[start..start + clustered.header.tile_data_length].to_vec();
+
+        assert_eq!(header.clustered, 1);
+        assert_eq!(header.num_tile_contents, 3);
+        assert_eq!(tile_data, b"AAAABBCCC");
+        let offsets: Vec<usize> = entries.iter().map(|entry| entry.offset).collect();
+        assert_eq!(offsets, [0, 4, 6, 0]);
+        assert_eq!(clustered.get_tile(1, 0, 1).unwrap(), Some(&b"CCC"[..]));
+        assert_eq!(clustered.get_tile(1, 1, 1).unwrap(), Some(&b"AAAA"[..]));
+    }
+}
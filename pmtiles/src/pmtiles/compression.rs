@@ -0,0 +1,84 @@
+use std::io::{self, Read, Write};
+
+use flate2::Compression as GzLevel;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+use super::types::Compression;
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+
+pub fn compress(data: &[u8], compression: Compression) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        },
+        Compression::Brotli => {
+            let mut compressed = Vec::new();
+            {
+                let mut encoder = brotli::CompressorWriter::new(&mut compressed, BROTLI_BUFFER_SIZE, BROTLI_QUALITY, BROTLI_WINDOW_SIZE);
+                encoder.write_all(data)?;
+            }
+            Ok(compressed)
+        },
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::encode_all(data, 0),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(unsupported(compression)),
+        Compression::Unknown => Err(unsupported(compression)),
+    }
+}
+
+pub fn decompress(data: &[u8], compression: Compression) -> io::Result<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Gzip => {
+            let mut decoder = GzDecoder::new(data);
+            let mut decompressed = Vec::new();
+            decoder.read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        },
+        Compression::Brotli => {
+            let mut decoder = brotli::Decompressor::new(data, BROTLI_BUFFER_SIZE);
+            let mut decompressed = Vec::new();
+            decoder.read_to_end(&mut decompressed)?;
+            Ok(decompressed)
+        },
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => zstd::decode_all(data),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(unsupported(compression)),
+        Compression::Unknown => Err(unsupported(compression)),
+    }
+}
+
+fn unsupported(compression: Compression) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("Unsupported compression: {}", compression)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let data = b"{\"name\":\"optimal_bvmap-v1\"}";
+        for compression in [Compression::None, Compression::Gzip, Compression::Brotli, Compression::Zstd] {
+            if cfg!(not(feature = "zstd")) && compression == Compression::Zstd {
+                assert!(compress(data, compression).is_err());
+                continue;
+            }
+            let compressed = compress(data, compression).unwrap();
+            assert_eq!(decompress(&compressed, compression).unwrap(), data, "{}", compression);
+        }
+        assert!(compress(data, Compression::Unknown).is_err());
+    }
+}
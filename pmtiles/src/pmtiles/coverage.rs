@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use serde_json::{Value as JsonValue, json};
+
+use super::PMTiles;
+use crate::tileid::{MAX_ZOOM, TileId, tile_position_to_lon_lat};
+
+/// Deepest zoom rendered by `Coverage::to_png`, i.e. 16384 pixels per side.
+pub const MAX_PNG_ZOOM: u8 = 14;
+
+/// The addressed tiles of one zoom level, merged into runs of horizontally adjacent tiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+    pub zoom: u8,
+    /// (y, first x, last x), sorted by y and then x.
+    pub runs: Vec<(u32, u32, u32)>,
+}
+
+impl Coverage {
+    /// Merges tiles given as (x, y) in any order.
+    pub fn from_tiles(zoom: u8, mut tiles: Vec<(u32, u32)>) -> Self {
+        tiles.sort_unstable_by_key(|&(x, y)| (y, x));
+        tiles.dedup();
+        let mut runs: Vec<(u32, u32, u32)> = Vec::new();
+        for (x, y) in tiles {
+            match runs.last_mut() {
+                Some((run_y, _, last_x)) if *run_y == y && *last_x + 1 == x => *last_x = x,
+                _ => runs.push((y, x, x)),
+            }
+        }
+        Coverage { zoom, runs }
+    }
+
+    pub fn num_tiles(&self) -> u64 {
+        self.runs.iter().map(|&(_, first_x, last_x)| (last_x - first_x) as u64 + 1).sum()
+    }
+
+    /// A FeatureCollection with one rectangle per run.
+    pub fn to_geojson(&self) -> JsonValue {
+        let features: Vec<JsonValue> = self.runs.iter().map(|&(y, first_x, last_x)| {
+            let corner = |x: u32, y: u32| {
+                let (lon, lat) = tile_position_to_lon_lat(self.zoom, x as f64, y as f64);
+                json!([lon, lat])
+            };
+            // 外周は反時計回り (RFC 7946)
+            let ring = [
+                corner(first_x, y + 1), corner(last_x + 1, y + 1), corner(last_x + 1, y),
+                corner(first_x, y), corner(first_x, y + 1),
+            ];
+            json!({
+                "type": "Feature",
+                "properties": { "z": self.zoom, "y": y, "min_x": first_x, "max_x": last_x, "tiles": last_x - first_x + 1 },
+                "geometry": { "type": "Polygon", "coordinates": [ring] },
+            })
+        }).collect();
+        json!({ "type": "FeatureCollection", "features": features })
+    }
+
+    /// A grayscale PNG with one pixel per tile: white where a tile exists, black elsewhere.
+    #[cfg(feature = "raster")]
+    pub fn to_png(&self) -> io::Result<Vec<u8>> {
+        use image::{GrayImage, ImageFormat, Luma};
+
+        if self.zoom > MAX_PNG_ZOOM {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Coverage PNGs are limited to zoom {}, got {}", MAX_PNG_ZOOM, self.zoom)
+            ));
+        }
+        let size = 1u32 << self.zoom;
+        let mut image = GrayImage::new(size, size);
+        for &(y, first_x, last_x) in &self.runs {
+            for x in first_x..=last_x {
+                image.put_pixel(x, y, Luma([255]));
+            }
+        }
+        let mut data = Vec::new();
+        image.write_to(&mut io::Cursor::new(&mut data), ImageFormat::Png).map_err(super::raster::to_io_error)?;
+        Ok(data)
+    }
+}
+
+/// Adds one tile to the runs of its row, merging with the runs it touches.
+fn add_to_runs(runs: &mut BTreeMap<(u32, u32), u32>, x: u32, y: u32) {
+    let mut first_x = x;
+    if let Some((&(run_y, run_first_x), &run_last_x)) = runs.range(..=(y, x)).next_back()
+        && run_y == y && run_last_x as u64 + 1 >= x as u64 {
+        if run_last_x >= x {
+            return;
+        }
+        first_x = run_first_x;
+    }
+    let last_x = match x.checked_add(1).and_then(|next_x| runs.remove(&(y, next_x))) {
+        Some(next_last_x) => next_last_x,
+        None => x,
+    };
+    runs.insert((y, first_x), last_x);
+}
+
+impl PMTiles {
+    /// Collects the addressed tiles at `zoom` from the (leaf) directories.
+    pub fn coverage(&self, zoom: u8) -> io::Result<Coverage> {
+        if zoom > MAX_ZOOM {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Zoom {} is beyond {}", zoom, MAX_ZOOM)));
+        }
+        // このズームのTileIDの範囲
+        let start = TileId::encode(zoom, 0, 0).value();
+        let end = start + (1u64 << zoom) * (1u64 << zoom);
+        // (y, 最初のx) -> 最後のx。タイルを並べずに行ごとの区間として直接まとめる
+        let mut runs: BTreeMap<(u32, u32), u32> = BTreeMap::new();
+        for entry in self.tile_entries_in(start..end)? {
+            let first = entry.tileid.value().max(start);
+            let last = (entry.tileid.value() + entry.run_length as u64).min(end);
+            for tile_id in first..last {
+                let (_, x, y) = TileId::new(tile_id).decode();
+                add_to_runs(&mut runs, x, y);
+            }
+        }
+        Ok(Coverage { zoom, runs: runs.into_iter().map(|((y, first_x), last_x)| (y, first_x, last_x)).collect() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::testing::{open_archive, test_header};
+    use crate::pmtiles::types::{Compression, TileType};
+
+    #[test]
+    fn coverage_of_archive() {
+        // z1の(0,1)だけが欠けている
+        let tiles = [(0, 0, 0), (1, 0, 0), (1, 1, 1), (1, 1, 0)].map(|(z, x, y)| (TileId::encode(z, x, y), b"tile"));
+        let pmtiles = open_archive(test_header(TileType::Unknown, Compression::None), "{}", tiles);
+
+        assert_eq!(pmtiles.coverage(0).unwrap().runs, [(0, 0, 0)]);
+        let coverage = pmtiles.coverage(1).unwrap();
+        assert_eq!(coverage.runs, [(0, 0, 1), (1, 1, 1)]);
+        assert_eq!(coverage.num_tiles(), 3);
+        assert!(pmtiles.coverage(2).unwrap().runs.is_empty());
+        assert!(pmtiles.coverage(MAX_ZOOM + 1).is_err());
+
+        let geojson = coverage.to_geojson();
+        assert_eq!(geojson["features"].as_array().unwrap().len(), 2);
+        assert_eq!(geojson["features"][0]["properties"]["tiles"], 2);
+        let ring = &geojson["features"][1]["geometry"]["coordinates"][0];
+        assert_eq!((&ring[0], &ring[2]), (&json!([0.0, -85.0511287798066]), &json!([180.0, 0.0])));
+
+        #[cfg(feature = "raster")]
+        {
+            let image = image::load_from_memory(&coverage.to_png().unwrap()).unwrap().to_luma8();
+            assert_eq!(image.dimensions(), (2, 2));
+            assert_eq!(image.as_raw(), &[255, 255, 0, 255]);
+            assert!(Coverage::from_tiles(MAX_PNG_ZOOM + 1, vec![]).to_png().is_err());
+        }
+    }
+
+    #[test]
+    fn coverage_merges_runs_across_entries() {
+        // z5を全て埋める。3タイルずつ同じ内容にしてrun_lengthの付いたエントリにする
+        let start = TileId::encode(5, 0, 0).value();
+        let tiles = (start..start + 1024).map(|tile_id| (TileId::new(tile_id), (tile_id / 3).to_le_bytes()));
+        let pmtiles = open_archive(test_header(TileType::Unknown, Compression::None), "{}", tiles);
+
+        let coverage = pmtiles.coverage(5).unwrap();
+        assert_eq!(coverage.runs, (0..32).map(|y| (y, 0, 31)).collect::<Vec<_>>());
+        assert_eq!(coverage.num_tiles(), 1024);
+        assert!(pmtiles.coverage(4).unwrap().runs.is_empty());
+        assert!(pmtiles.coverage(6).unwrap().runs.is_empty());
+    }
+}
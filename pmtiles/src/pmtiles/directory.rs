@@ -1,9 +1,18 @@
-use std::io::{self, Read};
-use flate2::read::GzDecoder;
+use std::io;
 use std::fmt;
 
-use crate::protobufs::decode_varint;
-use crate::tileid::TileId;
+use super::compression::{compress, decompress};
+use super::header::HEADER_SIZE;
+use super::types::Compression;
+use crate::protobufs::{VarintReader, encode_varint};
+use crate::tileid::{MAX_TILE_ID_END, MAX_ZOOM, TileId};
+
+/// The header and the root directory must fit in the first 16 KiB of an archive.
+const MAX_ROOT_SIZE: usize = 16_384 - HEADER_SIZE;
+const MIN_LEAF_SIZE: usize = 4096;
+/// Entry count from which `build_layout` goes straight to leaf directories instead of
+/// first compressing every entry into the root, as the reference writer does.
+const MAX_ROOT_ENTRIES: usize = 16_384;
 
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -17,6 +26,11 @@ pub struct DirectoryEntry {
 
 #[allow(unused)]
 impl DirectoryEntry {
+    /// `run_length` of 0 marks an entry pointing to a leaf directory.
+    pub fn new(tileid: TileId, offset: usize, length: usize, run_length: usize) -> Self {
+        DirectoryEntry { delta_encoded_tileid: 0, tileid, run_length, length, offset }
+    }
+
     pub fn print_info(&self) {
         println!("{:?}", self);
     }
@@ -41,86 +55,275 @@ pub struct Directory {
     pub entries: Vec<DirectoryEntry>,
 }
 
+/// Serialized root and leaf directories produced by `Directory::build_layout`.
+#[derive(Debug)]
+pub struct DirectoryLayout {
+    pub root: Vec<u8>,
+    pub leaves: Vec<u8>,
+    pub num_leaves: usize,
+}
+
 impl Directory {
-    pub fn parse_compressed(data: &[u8]) -> io::Result<Self> {
-        let mut decoder = GzDecoder::new(data);
-        let mut data_uncompressed = Vec::new();
-        decoder.read_to_end(&mut data_uncompressed)?;
+    /// Creates a directory from entries sorted by TileID, filling in the delta-encoded IDs.
+    pub fn new(mut entries: Vec<DirectoryEntry>) -> Self {
+        let mut last_tile_id = 0;
+        for entry in entries.iter_mut() {
+            entry.delta_encoded_tileid = entry.tileid.value() - last_tile_id;
+            last_tile_id = entry.tileid.value();
+        }
+        Directory { entries }
+    }
 
+    pub fn parse_compressed(data: &[u8], compression: Compression) -> io::Result<Self> {
+        let data_uncompressed = decompress(data, compression)?;
         Self::parse(&data_uncompressed)
     }
 
     pub fn parse(data: &[u8]) -> io::Result<Self> {
-        let (value, mut offset) = decode_varint(&data)
-            .expect("Number of entries is encoded as a little-endian varible-width integer");
-        let num_of_entries = value;
+        let mut reader = VarintReader::new(data);
+        let count = read_entry_count(&mut reader, data.len())?;
+
+        // 列ごとに読みながらエントリを直接埋める
+        let mut entries = Vec::with_capacity(count);
+        let mut last_tile_id: u64 = 0;
+        for _ in 0..count {
+            let delta: u64 = read_value(&mut reader, "TileID delta")?;
+            last_tile_id = last_tile_id.checked_add(delta).ok_or_else(overflow)?;
+            entries.push(DirectoryEntry { delta_encoded_tileid: delta, tileid: TileId::new(last_tile_id), run_length: 0, length: 0, offset: 0 });
+        }
+        for entry in entries.iter_mut() {
+            entry.run_length = read_value::<u32>(&mut reader, "Run length")? as usize;
+            check_tile_range(entry.tileid.value(), entry.run_length as u64)?;
+        }
+        for entry in entries.iter_mut() {
+            entry.length = read_value::<u32>(&mut reader, "Length")? as usize;
+        }
+        let mut next_offset: u64 = 0;
+        for (i, entry) in entries.iter_mut().enumerate() {
+            let raw: u64 = read_value(&mut reader, "Offset")?;
+            let offset = if raw == 0 && i > 0 { next_offset } else { raw.saturating_sub(1) };
+            next_offset = offset.checked_add(entry.length as u64).ok_or_else(overflow)?;
+            entry.offset = offset as usize;
+        }
+
+        Ok(Directory { entries })
+    }
+
+    /// Finds the entry covering `tile_id`: either a tile entry whose run contains it,
+    /// or the leaf directory entry (run_length 0) that may contain it.
+    pub fn find_entry(&self, tile_id: TileId) -> Option<&DirectoryEntry> {
+        // tile_id以下で最大のTileIDを持つエントリを二分探索する
+        let index = self.entries.partition_point(|entry| entry.tileid.value() <= tile_id.value());
+        let entry = self.entries.get(index.checked_sub(1)?)?;
+        if entry.run_length == 0 || tile_id.value() < entry.tileid.value() + entry.run_length as u64 {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    /// Encodes the directory in the columnar varint format read by `parse`.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        encode_varint(self.entries.len() as u64, &mut buffer);
+
+        let mut last_tile_id = 0;
+        for entry in &self.entries {
+            encode_varint(entry.tileid.value() - last_tile_id, &mut buffer);
+            last_tile_id = entry.tileid.value();
+        }
+        for entry in &self.entries {
+            encode_varint(entry.run_length as u64, &mut buffer);
+        }
+        for entry in &self.entries {
+            encode_varint(entry.length as u64, &mut buffer);
+        }
+
+        // 直前のエントリと連続している場合は0、そうでなければoffset+1を書く
+        let mut next_offset = None;
+        for entry in &self.entries {
+            if next_offset == Some(entry.offset) {
+                encode_varint(0, &mut buffer);
+            } else {
+                encode_varint(entry.offset as u64 + 1, &mut buffer);
+            }
+            next_offset = Some(entry.offset + entry.length);
+        }
+        buffer
+    }
 
-        println!("Number of Entries: {}", num_of_entries);
+    pub fn serialize_compressed(&self, compression: Compression) -> io::Result<Vec<u8>> {
+        compress(&self.serialize(), compression)
+    }
+
+    /// Splits tile entries into a root directory and leaf directories so that the
+    /// compressed root stays within the first 16 KiB of the archive.
+    pub fn build_layout(entries: &[DirectoryEntry], compression: Compression) -> io::Result<DirectoryLayout> {
+        if entries.len() < MAX_ROOT_ENTRIES {
+            let root = Directory::new(entries.to_vec()).serialize_compressed(compression)?;
+            if root.len() <= MAX_ROOT_SIZE {
+                return Ok(DirectoryLayout { root, leaves: Vec::new(), num_leaves: 0 });
+            }
+        }
+
+        let mut leaf_size = (entries.len() / 3500).max(MIN_LEAF_SIZE);
+        loop {
+            let layout = Self::build_leaves(entries, leaf_size, compression)?;
+            if layout.root.len() <= MAX_ROOT_SIZE {
+                return Ok(layout);
+            }
+            leaf_size += leaf_size / 5;
+        }
+    }
 
-        let (delta_encoded_tileids, size) = read_varints(&data[offset..], num_of_entries)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        offset += size;
+    fn build_leaves(entries: &[DirectoryEntry], leaf_size: usize, compression: Compression) -> io::Result<DirectoryLayout> {
+        let mut root_entries = Vec::new();
+        let mut leaves = Vec::new();
+        for chunk in entries.chunks(leaf_size) {
+            let leaf = Directory::new(chunk.to_vec()).serialize_compressed(compression)?;
+            root_entries.push(DirectoryEntry::new(chunk[0].tileid, leaves.len(), leaf.len(), 0));
+            leaves.extend_from_slice(&leaf);
+        }
+        let num_leaves = root_entries.len();
+        let root = Directory::new(root_entries).serialize_compressed(compression)?;
+        Ok(DirectoryLayout { root, leaves, num_leaves })
+    }
+}
 
-        let (run_lengths, size) = read_varints(&data[offset..], num_of_entries)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        offset += size;
+/// Struct-of-arrays form of a directory: one column per field, TileIDs and offsets
+/// already resolved, and no `DirectoryEntry` built until one is looked up.
+/// Run lengths and lengths are stored as u32, like the reference implementation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactDirectory {
+    tile_ids: Vec<u64>,
+    run_lengths: Vec<u32>,
+    lengths: Vec<u32>,
+    offsets: Vec<u64>,
+}
 
-        let (lengths, size) = read_varints(&data[offset..], num_of_entries)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        offset += size;
+impl CompactDirectory {
+    pub fn parse_compressed(data: &[u8], compression: Compression) -> io::Result<Self> {
+        Self::parse(&decompress(data, compression)?)
+    }
 
-        let (offsets, _) = read_varints(&data[offset..], num_of_entries)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let mut reader = VarintReader::new(data);
+        let count = read_entry_count(&mut reader, data.len())?;
 
-        let mut entries = Vec::with_capacity(num_of_entries as usize);
+        // 各列は1回の読み込みで確保済みのVecに直接埋める
+        let mut tile_ids = Vec::with_capacity(count);
         let mut last_tile_id: u64 = 0;
+        for _ in 0..count {
+            last_tile_id = last_tile_id.checked_add(read_value(&mut reader, "TileID delta")?).ok_or_else(overflow)?;
+            tile_ids.push(last_tile_id);
+        }
+        let mut run_lengths = Vec::with_capacity(count);
+        for &tile_id in &tile_ids {
+            let run_length: u32 = read_value(&mut reader, "Run length")?;
+            check_tile_range(tile_id, run_length as u64)?;
+            run_lengths.push(run_length);
+        }
+        let mut lengths = Vec::with_capacity(count);
+        for _ in 0..count {
+            lengths.push(read_value::<u32>(&mut reader, "Length")?);
+        }
+        let mut offsets = Vec::with_capacity(count);
+        let mut next_offset: u64 = 0;
+        for (i, &length) in lengths.iter().enumerate() {
+            let raw: u64 = read_value(&mut reader, "Offset")?;
+            let offset = if raw == 0 && i > 0 { next_offset } else { raw.saturating_sub(1) };
+            next_offset = offset.checked_add(length as u64).ok_or_else(overflow)?;
+            offsets.push(offset);
+        }
 
-        let mut last_offset: u64 = 0;
-        let mut last_length: u64 = 0;
+        Ok(CompactDirectory { tile_ids, run_lengths, lengths, offsets })
+    }
 
-        for i in 0..num_of_entries as usize {
-            let tile_id_value = last_tile_id + delta_encoded_tileids[i];
+    pub fn len(&self) -> usize {
+        self.tile_ids.len()
+    }
 
-            let current_raw_offset = offsets[i];
-            let actual_offset = if current_raw_offset == 0 && i > 0 {
-                last_offset + last_length
-            } else {
-                current_raw_offset.saturating_sub(1)
-            };
+    pub fn is_empty(&self) -> bool {
+        self.tile_ids.is_empty()
+    }
+
+    pub fn entry(&self, index: usize) -> Option<DirectoryEntry> {
+        let tile_id = *self.tile_ids.get(index)?;
+        let previous = index.checked_sub(1).map_or(0, |previous| self.tile_ids[previous]);
+        Some(DirectoryEntry {
+            delta_encoded_tileid: tile_id - previous,
+            tileid: TileId::new(tile_id),
+            run_length: self.run_lengths[index] as usize,
+            length: self.lengths[index] as usize,
+            offset: self.offsets[index] as usize,
+        })
+    }
 
-            entries.push(DirectoryEntry {
-                delta_encoded_tileid: delta_encoded_tileids[i],
-                tileid: TileId::new(tile_id_value),
-                run_length: run_lengths[i] as usize,
-                length: lengths[i] as usize,
-                offset: actual_offset as usize,
-            });
-            last_tile_id = tile_id_value;
-            last_offset = actual_offset;
-            last_length = lengths[i];
+    pub fn entries(&self) -> impl Iterator<Item = DirectoryEntry> + '_ {
+        (0..self.len()).filter_map(|index| self.entry(index))
+    }
+
+    /// Same as `Directory::find_entry`, searching the TileID column only.
+    pub fn find_entry(&self, tile_id: TileId) -> Option<DirectoryEntry> {
+        let index = self.tile_ids.partition_point(|&id| id <= tile_id.value()).checked_sub(1)?;
+        let run_length = self.run_lengths[index] as u64;
+        if run_length == 0 || tile_id.value() < self.tile_ids[index] + run_length {
+            self.entry(index)
+        } else {
+            None
         }
+    }
+}
+
+impl From<CompactDirectory> for Directory {
+    fn from(compact: CompactDirectory) -> Self {
+        Directory { entries: compact.entries().collect() }
+    }
+}
 
-        Ok(Directory {entries})
+fn overflow() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Directory entry overflows 64 bits")
+}
 
+fn read_entry_count(reader: &mut VarintReader, data_len: usize) -> io::Result<usize> {
+    let num_of_entries = reader.read_varint()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    log::debug!("Number of Entries: {}", num_of_entries);
+    // 各エントリは少なくとも4バイト使うので、それ以上の件数は壊れている
+    if num_of_entries > (data_len / 4) as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Directory of {} bytes cannot hold {} entries", data_len, num_of_entries)
+        ));
     }
+    Ok(num_of_entries as usize)
 }
 
-fn read_varints(buffer: &[u8], count: u64) -> Result<(Vec<u64>, usize), &'static str> {
-    let mut values: Vec<u64> = Vec::new();
-    let mut size_read: usize = 0;
-    for _ in 0..count {
-        let (value, size) = decode_varint(&buffer[(size_read)..])?;
-        //println!("Value: {}, Bytes read: {}", value, size);
-        values.push(value);
-        size_read += size;
+fn check_tile_range(tile_id: u64, run_length: u64) -> io::Result<()> {
+    if tile_id.saturating_add(run_length) > MAX_TILE_ID_END {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("TileID {} with run length {} is beyond zoom {}", tile_id, run_length, MAX_ZOOM)
+        ));
     }
-    Ok((values, size_read))
+    Ok(())
+}
 
+fn read_value<T: TryFrom<u64>>(reader: &mut VarintReader, name: &str) -> io::Result<T> {
+    let value = reader.read_varint().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    T::try_from(value).map_err(|_| io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} {} does not fit in {} bits", name, value, std::mem::size_of::<T>() * 8)
+    ))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    // proptestはwasm32では使えない
+    #[cfg(not(target_arch = "wasm32"))]
+    use proptest::prelude::*;
 
     const DIR_DATA: [u8; 17] = [
         0x04, // Number of entries: 3
@@ -169,4 +372,120 @@ mod tests {
 
 
     }
+
+    #[test]
+    fn test_find_entry() {
+        let directory = Directory::parse(&DIR_DATA).expect("should parse directory data");
+        assert_eq!(directory.find_entry(TileId::new(0)), None);
+        assert_eq!(directory.find_entry(TileId::new(3)), Some(&directory.entries[1]));
+        assert_eq!(directory.find_entry(TileId::new(4)), None);
+        // run_lengthが0のエントリはリーフディレクトリを指す
+        assert_eq!(directory.find_entry(TileId::new(8)), Some(&directory.entries[2]));
+        assert_eq!(directory.find_entry(TileId::new(100)), Some(&directory.entries[3]));
+    }
+
+    #[test]
+    fn test_serialize_directory() {
+        let directory = Directory::parse(&DIR_DATA).expect("should parse directory data");
+        assert_eq!(directory.serialize(), DIR_DATA);
+
+        let built = Directory::new(vec![
+            DirectoryEntry::new(TileId::new(5), 0, 100, 1),
+            DirectoryEntry::new(TileId::new(6), 100, 50, 3),
+            DirectoryEntry::new(TileId::new(9), 0, 100, 1),
+        ]);
+        let reparsed = Directory::parse(&built.serialize()).expect("should parse serialized directory");
+        assert_eq!(reparsed.entries, built.entries);
+    }
+
+    #[test]
+    fn test_build_layout_with_leaves() {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        let mut seed: u64 = 1;
+        for i in 0..100_000u64 {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let length = 100 + (seed >> 52) as usize;
+            entries.push(DirectoryEntry::new(TileId::new(i * 2), offset, length, 1));
+            // ときどき離れた位置にタイルを置き、offsetの0省略が効かないケースを作る
+            offset += length + if seed.is_multiple_of(7) { 13 } else { 0 };
+        }
+
+        let layout = Directory::build_layout(&entries, Compression::Gzip).expect("should build layout");
+        assert!(layout.root.len() <= MAX_ROOT_SIZE);
+        assert!(layout.num_leaves > 1);
+
+        let root = Directory::parse_compressed(&layout.root, Compression::Gzip).expect("should parse root");
+        assert_eq!(root.entries.len(), layout.num_leaves);
+        let mut leaf_entries = Vec::new();
+        for root_entry in &root.entries {
+            assert_eq!(root_entry.run_length, 0);
+            let leaf_data = &layout.leaves[root_entry.offset .. root_entry.offset + root_entry.length];
+            let leaf = Directory::parse_compressed(leaf_data, Compression::Gzip).expect("should parse leaf");
+            leaf_entries.extend(leaf.entries);
+        }
+        let as_tuple = |entry: &DirectoryEntry| (entry.tileid, entry.offset, entry.length, entry.run_length);
+        assert!(leaf_entries.iter().map(as_tuple).eq(entries.iter().map(as_tuple)));
+    }
+
+    #[test]
+    fn parse_rejects_corrupt_directories() {
+        // 件数がデータ長に見合わない
+        assert!(Directory::parse(&[0xff, 0xff, 0xff, 0xff, 0x0f]).is_err());
+        // TileIDの合計が64bitを超える
+        let mut data = vec![2];
+        encode_varint(u64::MAX, &mut data);
+        encode_varint(u64::MAX, &mut data);
+        data.extend([1, 1, 1, 1, 1, 0]);
+        assert!(Directory::parse(&data).is_err());
+        // 長さがu32に収まらない
+        let mut data = vec![1, 1, 1];
+        encode_varint(u32::MAX as u64 + 1, &mut data);
+        data.push(1);
+        assert!(Directory::parse(&data).is_err());
+    }
+
+    #[test]
+    fn compact_directory_lookup() {
+        let compact = CompactDirectory::parse(&DIR_DATA).expect("should parse directory data");
+        let directory = Directory::parse(&DIR_DATA).expect("should parse directory data");
+        assert_eq!(compact.len(), 4);
+        assert!(compact.entries().eq(directory.entries.iter().cloned()));
+        for tile_id in 0..120 {
+            let tile_id = TileId::new(tile_id);
+            assert_eq!(compact.find_entry(tile_id).as_ref(), directory.find_entry(tile_id));
+        }
+        assert_eq!(compact.entry(4), None);
+        assert!(CompactDirectory::parse(&[0]).unwrap().is_empty());
+    }
+
+    /// Entries with increasing TileIDs, sometimes contiguous and sometimes not.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn entries_strategy() -> impl Strategy<Value = Vec<DirectoryEntry>> {
+        prop::collection::vec((1u64..1000, 0usize..5, 0usize..10_000, 0usize..3), 0..200).prop_map(|columns| {
+            let (mut tile_id, mut offset) = (0, 0);
+            columns.into_iter().map(|(delta, run_length, length, gap)| {
+                tile_id += delta;
+                offset += gap * 7;
+                let entry = DirectoryEntry::new(TileId::new(tile_id), offset, length, run_length);
+                offset += length;
+                entry
+            }).collect()
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    proptest! {
+        #[test]
+        fn parse_never_panics(data in prop::collection::vec(any::<u8>(), 0..512)) {
+            let _ = Directory::parse(&data);
+        }
+
+        #[test]
+        fn serialize_round_trip(entries in entries_strategy()) {
+            let directory = Directory::new(entries);
+            let reparsed = Directory::parse(&directory.serialize()).unwrap();
+            prop_assert_eq!(reparsed.entries, directory.entries);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::header::{Header, HEADER_SIZE};
+use super::metadata::Metadata;
+
+/// Header fields that can be rewritten without touching directories or tile data.
+/// `None` keeps the current value.
+#[derive(Debug, Default, Clone)]
+pub struct HeaderEdit {
+    pub min_position: Option<(f64, f64)>,
+    pub max_position: Option<(f64, f64)>,
+    pub center_position: Option<(f64, f64)>,
+    pub center_zoom: Option<u8>,
+    pub min_zoom: Option<u8>,
+    pub max_zoom: Option<u8>,
+}
+
+impl HeaderEdit {
+    fn apply(&self, header: &mut Header) {
+        if let Some(position) = self.min_position {
+            header.min_position = position;
+        }
+        if let Some(position) = self.max_position {
+            header.max_position = position;
+        }
+        if let Some(position) = self.center_position {
+            header.center_position = position;
+        }
+        if let Some(zoom) = self.center_zoom {
+            header.center_zoom = zoom;
+        }
+        if let Some(zoom) = self.min_zoom {
+            header.min_zoom = zoom;
+        }
+        if let Some(zoom) = self.max_zoom {
+            header.max_zoom = zoom;
+        }
+    }
+}
+
+/// Rewrites header fields and, optionally, the metadata JSON of an existing archive.
+///
+/// Directories and tile data are left untouched. The new metadata is written over the
+/// old section when it fits; otherwise it is appended to the end of the file and
+/// `metadata_offset` is moved there. Nothing is written when the edited header fails
+/// `Header::validate`, so the archive keeps opening.
+pub fn edit_in_place(file_path: &str, edit: &HeaderEdit, metadata: Option<&Metadata>) -> io::Result<Header> {
+    let mut file = File::options().read(true).write(true).open(file_path)?;
+
+    let mut header_data = [0u8; HEADER_SIZE];
+    file.read_exact(&mut header_data)?;
+    let mut header = Header::parse(&header_data)?;
+    edit.apply(&mut header);
+
+    let mut file_len = file.seek(SeekFrom::End(0))? as usize;
+    let metadata = metadata.map(|metadata| metadata.to_compressed(header.internal_compression)).transpose()?;
+    if let Some(compressed) = &metadata {
+        // 元の領域に収まらない場合はファイル末尾に移動する
+        if compressed.len() > header.metadata_length {
+            header.metadata_offset = file_len;
+            file_len += compressed.len();
+        }
+        header.metadata_length = compressed.len();
+    }
+    let warnings = header.validate(file_len)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Edited header is invalid: {}", e)))?;
+    for warning in warnings {
+        log::warn!("{}", warning);
+    }
+
+    if let Some(compressed) = metadata {
+        file.seek(SeekFrom::Start(header.metadata_offset as u64))?;
+        file.write_all(&compressed)?;
+    }
+
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header.to_bytes())?;
+    file.flush()?;
+
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::PMTiles;
+    use crate::pmtiles::testing::{TempPath, test_header, write_archive};
+    use crate::pmtiles::types::{Compression, TileType};
+    use crate::tileid::TileId;
+
+    const TILE_DATA: &[u8] = b"tile-bytes";
+
+    #[test]
+    fn edit_header_and_grow_metadata() {
+        let temp_path = TempPath::new("edit");
+        let file_path = temp_path.as_str();
+        write_archive(&temp_path, test_header(TileType::MVT, Compression::None), "{\"name\":\"a\"}", [(TileId::new(0), TILE_DATA)]);
+
+        let edit = HeaderEdit {
+            center_position: Some((139.767125, 35.681236)),
+            center_zoom: Some(12),
+            max_zoom: Some(14),
+            ..Default::default()
+        };
+        let long_attribution = "x".repeat(2000);
+        let metadata = Metadata::from_json(&format!("{{\"name\":\"a\",\"attribution\":\"{}\"}}", long_attribution)).unwrap();
+        let edited = edit_in_place(file_path, &edit, Some(&metadata)).unwrap();
+
+        let pmtiles = PMTiles::open(file_path).unwrap();
+
+        assert_eq!(pmtiles.header.center_position, (139.767125, 35.681236));
+        assert_eq!(pmtiles.header.center_zoom, 12);
+        assert_eq!(pmtiles.header.max_zoom, 14);
+        assert_eq!(pmtiles.header.metadata_offset, edited.metadata_offset);
+        assert!(pmtiles.header.metadata_offset > pmtiles.header.tile_data_offset);
+        assert_eq!(pmtiles.metadata, metadata);
+
+        let tile_data_offset = pmtiles.header.tile_data_offset;
+        assert_eq!(&pmtiles.data[tile_data_offset..tile_data_offset + TILE_DATA.len()], TILE_DATA);
+    }
+
+    #[test]
+    fn reject_inverted_zooms() {
+        let temp_path = TempPath::new("edit_zoom");
+        let file_path = temp_path.as_str();
+        write_archive(&temp_path, test_header(TileType::MVT, Compression::None), "{}", [(TileId::new(0), TILE_DATA)]);
+
+        let edit = HeaderEdit { min_zoom: Some(5), ..Default::default() };
+        let result = edit_in_place(file_path, &edit, None);
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reject_headers_that_would_not_open() {
+        let temp_path = TempPath::new("edit_invalid");
+        let file_path = temp_path.as_str();
+        write_archive(&temp_path, test_header(TileType::MVT, Compression::None), "{}", [(TileId::new(0), TILE_DATA)]);
+        let before = std::fs::read(file_path).unwrap();
+
+        let edit = HeaderEdit { max_zoom: Some(32), ..Default::default() };
+        let metadata = Metadata::from_json(&format!("{{\"name\":\"{}\"}}", "x".repeat(2000))).unwrap();
+        let result = edit_in_place(file_path, &edit, Some(&metadata));
+        let after = std::fs::read(file_path).unwrap();
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(after, before);
+    }
+}
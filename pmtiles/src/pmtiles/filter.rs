@@ -0,0 +1,300 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io;
+
+use serde_json::Value as JsonValue;
+
+use super::PMTiles;
+use super::compression::{compress, decompress};
+use super::header::Header;
+use super::metadata::Metadata;
+use super::types::TileType;
+use super::writer::write_to_file;
+use crate::mvt::{Feature, Tile, Value};
+use crate::tileid::TileId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// One `key op literal` term of a `FeatureFilter`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub key: String,
+    pub comparison: Comparison,
+    pub value: Value,
+}
+
+/// Conditions joined by `&&`, e.g. `class == "motorway" && rank >= 5`.
+/// Literals are double-quoted strings, numbers, `true` or `false`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureFilter {
+    pub conditions: Vec<Condition>,
+}
+
+const OPERATORS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Eq), ("!=", Comparison::Ne), ("<=", Comparison::Le),
+    (">=", Comparison::Ge), ("<", Comparison::Lt), (">", Comparison::Gt),
+];
+
+fn invalid_expression(expression: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid filter expression: {}", expression))
+}
+
+impl FeatureFilter {
+    pub fn parse(expression: &str) -> io::Result<Self> {
+        let conditions = split_terms(expression).into_iter().map(|term| {
+            // 文字列リテラルの中の演算子は無視する。2文字の演算子を先に探す
+            let before_literal = &term[..term.find('"').unwrap_or(term.len())];
+            let (index, operator, comparison) = OPERATORS.iter()
+                .find_map(|&(operator, comparison)| before_literal.find(operator).map(|index| (index, operator, comparison)))
+                .ok_or_else(|| invalid_expression(term))?;
+            let key = term[..index].trim();
+            let literal = term[index + operator.len()..].trim();
+            if key.is_empty() || literal.is_empty() {
+                return Err(invalid_expression(term));
+            }
+            Ok(Condition { key: key.to_string(), comparison, value: parse_literal(literal).ok_or_else(|| invalid_expression(term))? })
+        }).collect::<io::Result<_>>()?;
+        Ok(FeatureFilter { conditions })
+    }
+
+    /// A feature matches when every condition holds. A missing property only satisfies `!=`.
+    pub fn matches(&self, feature: &Feature) -> bool {
+        self.conditions.iter().all(|condition| {
+            let ordering = feature.property(&condition.key).and_then(|value| compare(value, &condition.value));
+            match (condition.comparison, ordering) {
+                (Comparison::Ne, ordering) => ordering != Some(Ordering::Equal),
+                (_, None) => false,
+                (Comparison::Eq, Some(ordering)) => ordering == Ordering::Equal,
+                (Comparison::Lt, Some(ordering)) => ordering == Ordering::Less,
+                (Comparison::Le, Some(ordering)) => ordering != Ordering::Greater,
+                (Comparison::Gt, Some(ordering)) => ordering == Ordering::Greater,
+                (Comparison::Ge, Some(ordering)) => ordering != Ordering::Less,
+            }
+        })
+    }
+}
+
+/// Splits `expression` at each `&&` outside double-quoted literals.
+fn split_terms(expression: &str) -> Vec<&str> {
+    let mut terms = Vec::new();
+    let (mut start, mut in_literal) = (0, false);
+    let bytes = expression.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => in_literal = !in_literal,
+            b'&' if !in_literal && bytes.get(i + 1) == Some(&b'&') => {
+                terms.push(expression[start..i].trim());
+                start = i + 2;
+                i += 1;
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+    terms.push(expression[start..].trim());
+    terms
+}
+
+fn parse_literal(literal: &str) -> Option<Value> {
+    if let Some(string) = literal.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+        return Some(Value::String(string.to_string()));
+    }
+    match literal {
+        "true" => Some(Value::Bool(true)),
+        "false" => Some(Value::Bool(false)),
+        _ => literal.parse::<f64>().ok().map(Value::Double),
+    }
+}
+
+/// Numbers compare by value whatever their encoding; strings and booleans only with their own kind.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+    }
+}
+
+/// What `filter_layers` changes. Layers are matched by their original name.
+#[derive(Debug, Default, Clone)]
+pub struct LayerFilter {
+    pub drop_layers: Vec<String>,
+    /// Old name to new name.
+    pub rename_layers: HashMap<String, String>,
+    /// Property keys removed from every feature.
+    pub drop_properties: Vec<String>,
+    /// Only features matching it are kept.
+    pub feature_filter: Option<FeatureFilter>,
+}
+
+impl LayerFilter {
+    pub fn apply(&self, tile: &mut Tile) {
+        tile.layers.retain(|layer| !self.drop_layers.contains(&layer.name));
+        for layer in &mut tile.layers {
+            if let Some(filter) = &self.feature_filter {
+                layer.features.retain(|feature| filter.matches(feature));
+            }
+            for feature in &mut layer.features {
+                feature.properties.retain(|(key, _)| !self.drop_properties.contains(key));
+            }
+            if let Some(name) = self.rename_layers.get(&layer.name) {
+                layer.name = name.clone();
+            }
+        }
+        tile.layers.retain(|layer| !layer.features.is_empty());
+    }
+
+    /// Applies the layer and property changes to `vector_layers` in the metadata.
+    pub fn apply_to_metadata(&self, metadata: &Metadata) -> io::Result<Metadata> {
+        let mut json: JsonValue = serde_json::from_str(metadata.json())?;
+        if let Some(vector_layers) = json.get_mut("vector_layers").and_then(JsonValue::as_array_mut) {
+            vector_layers.retain(|layer| !layer["id"].as_str().is_some_and(|id| self.drop_layers.iter().any(|name| name == id)));
+            for layer in vector_layers.iter_mut() {
+                if let Some(fields) = layer.get_mut("fields").and_then(JsonValue::as_object_mut) {
+                    fields.retain(|key, _| !self.drop_properties.contains(key));
+                }
+                if let Some(name) = layer["id"].as_str().and_then(|id| self.rename_layers.get(id)) {
+                    layer["id"] = JsonValue::from(name.as_str());
+                }
+            }
+        }
+        Metadata::from_json(&json.to_string())
+    }
+}
+
+/// Writes a copy of the MVT archive `input_path` with `filter` applied to every tile.
+/// Tiles left without any feature are omitted.
+pub fn filter_layers(input_path: &str, output_path: &str, filter: &LayerFilter) -> io::Result<Header> {
+    let input = PMTiles::open(input_path)?;
+    if input.header.tile_type != TileType::MVT {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Layers can only be filtered in MVT archives, not {}", input.header.tile_type)
+        ));
+    }
+    let entries = input.tile_entries()?;
+    let metadata = filter.apply_to_metadata(&input.metadata)?;
+
+    write_to_file(output_path, input.header.clone(), &metadata, |writer| {
+        input.for_each_unique_tile(&entries, |data| {
+            let mut tile = Tile::decode(&decompress(data, input.header.tile_compression)?)?;
+            filter.apply(&mut tile);
+            if tile.layers.is_empty() {
+                return Ok(None);
+            }
+            compress(&tile.encode(), input.header.tile_compression).map(Some)
+        }, |entry, data| {
+            if let Some(data) = data {
+                for i in 0..entry.run_length as u64 {
+                    writer.add_tile(TileId::new(entry.tileid.value() + i), data)?;
+                }
+            }
+            Ok(())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mvt::{GeomType, Layer};
+    use crate::pmtiles::testing::{TempPath, archive_bytes, test_header};
+    use crate::pmtiles::types::Compression;
+
+    fn feature(properties: &[(&str, Value)]) -> Feature {
+        Feature {
+            properties: properties.iter().map(|(key, value)| (key.to_string(), value.clone())).collect(),
+            geom_type: GeomType::Point,
+            geometry: vec![vec![(1, 1)]],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_and_match_expressions() {
+        let filter = FeatureFilter::parse(r#"class == "motorway" && rank >= 5"#).unwrap();
+        assert_eq!(filter.conditions[1], Condition { key: "rank".to_string(), comparison: Comparison::Ge, value: Value::Double(5.0) });
+        assert!(filter.matches(&feature(&[("class", Value::String("motorway".to_string())), ("rank", Value::Uint(5))])));
+        assert!(!filter.matches(&feature(&[("class", Value::String("motorway".to_string())), ("rank", Value::Sint(4))])));
+        assert!(!filter.matches(&feature(&[("rank", Value::Float(9.0))])));
+
+        let filter = FeatureFilter::parse("internal != true").unwrap();
+        assert!(filter.matches(&feature(&[])));
+        assert!(!filter.matches(&feature(&[("internal", Value::Bool(true))])));
+
+        assert!(FeatureFilter::parse("rank").is_err());
+        assert!(FeatureFilter::parse("rank >= ").is_err());
+        assert!(FeatureFilter::parse("name == motorway").is_err());
+    }
+
+    #[test]
+    fn parse_literals_containing_operators() {
+        let filter = FeatureFilter::parse(r#"name == "a<=b""#).unwrap();
+        assert_eq!(filter.conditions[0], Condition { key: "name".to_string(), comparison: Comparison::Eq, value: Value::String("a<=b".to_string()) });
+        let filter = FeatureFilter::parse(r#"label != "x==y""#).unwrap();
+        assert_eq!(filter.conditions[0], Condition { key: "label".to_string(), comparison: Comparison::Ne, value: Value::String("x==y".to_string()) });
+        let filter = FeatureFilter::parse(r#"kind > "<>""#).unwrap();
+        assert_eq!((filter.conditions[0].comparison, &filter.conditions[0].value), (Comparison::Gt, &Value::String("<>".to_string())));
+        assert!(FeatureFilter::parse(r#"name "a==b""#).is_err());
+    }
+
+    #[test]
+    fn parse_literals_containing_and() {
+        let filter = FeatureFilter::parse(r#"name == "R&&D" && rank >= 2"#).unwrap();
+        assert_eq!(filter.conditions.len(), 2);
+        assert_eq!(filter.conditions[0].value, Value::String("R&&D".to_string()));
+        assert_eq!(filter.conditions[1].key, "rank");
+        let filter = FeatureFilter::parse(r#"  label != "a && b == c""#).unwrap();
+        assert_eq!((filter.conditions[0].comparison, &filter.conditions[0].value), (Comparison::Ne, &Value::String("a && b == c".to_string())));
+        assert!(FeatureFilter::parse(r#"name == "R&&D"#).is_err());
+    }
+
+    #[test]
+    fn filter_archive() {
+        let (input, output) = (TempPath::new("filter_in"), TempPath::new("filter_out"));
+
+        let mut road = Layer::new("road");
+        road.features.push(feature(&[("name", Value::String("国道".to_string())), ("internal_id", Value::Uint(1)), ("rank", Value::Uint(9))]));
+        road.features.push(feature(&[("name", Value::String("私道".to_string())), ("internal_id", Value::Uint(2)), ("rank", Value::Uint(1))]));
+        let mut debug = Layer::new("debug");
+        debug.features.push(feature(&[]));
+        let tile = Tile { layers: vec![road, debug.clone()] };
+        let debug_only = Tile { layers: vec![debug] };
+
+        let metadata = r#"{"vector_layers":[
+            {"id":"road","fields":{"name":"String","internal_id":"Number","rank":"Number"}},
+            {"id":"debug","fields":{}}
+        ]}"#;
+        let tiles = [tile, debug_only].into_iter().enumerate()
+            .map(|(i, tile)| (TileId::new(i as u64), compress(&tile.encode(), Compression::Gzip).unwrap()));
+        std::fs::write(&input, archive_bytes(test_header(TileType::MVT, Compression::Gzip), metadata, tiles)).unwrap();
+
+        let filter = LayerFilter {
+            drop_layers: vec!["debug".to_string()],
+            rename_layers: HashMap::from([("road".to_string(), "roads".to_string())]),
+            drop_properties: vec!["internal_id".to_string()],
+            feature_filter: Some(FeatureFilter::parse("rank > 5").unwrap()),
+        };
+        let header = filter_layers(input.as_str(), output.as_str(), &filter).unwrap();
+        let pmtiles = PMTiles::open(output.as_str()).unwrap();
+
+        assert_eq!(header.num_addressed_tiles, 1);
+        let data = pmtiles.get_tile(0, 0, 0).unwrap().unwrap();
+        let filtered = Tile::decode(&decompress(data, Compression::Gzip).unwrap()).unwrap();
+        assert_eq!(filtered.layers.len(), 1);
+        assert_eq!(filtered.layers[0].name, "roads");
+        assert_eq!(filtered.layers[0].features, vec![feature(&[("name", Value::String("国道".to_string())), ("rank", Value::Uint(9))])]);
+
+        let metadata: JsonValue = serde_json::from_str(pmtiles.metadata.json()).unwrap();
+        assert_eq!(metadata["vector_layers"], serde_json::json!([{"id":"roads","fields":{"name":"String","rank":"Number"}}]));
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::io;
+
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::xxh3_64;
+
+use super::PMTiles;
+use crate::tileid::TileId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// Fast 64-bit non-cryptographic hash.
+    Xxh3,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Hashes `data`, returning the digest as lowercase hex.
+    pub fn hash(&self, data: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Xxh3 => format!("{:016x}", xxh3_64(data)),
+            HashAlgorithm::Sha256 => Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect(),
+        }
+    }
+}
+
+/// The hash of one directory entry's payload, as stored in the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileHash {
+    pub tile_id: TileId,
+    pub run_length: usize,
+    pub length: usize,
+    pub hash: String,
+}
+
+/// A payload shared by several tiles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateContent {
+    pub hash: String,
+    pub length: usize,
+    /// Number of addressed tiles with this payload.
+    pub count: u64,
+    pub sample_tile_ids: Vec<TileId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateReport {
+    pub num_addressed_tiles: u64,
+    pub num_distinct_hashes: u64,
+    /// Most duplicated payloads first.
+    pub top: Vec<DuplicateContent>,
+}
+
+impl DuplicateReport {
+    pub fn print_info(&self) {
+        println!("Duplicates: {} addressed tiles, {} distinct payloads", self.num_addressed_tiles, self.num_distinct_hashes);
+        for content in &self.top {
+            let samples: Vec<String> = content.sample_tile_ids.iter().map(|tile_id| {
+                let (z, x, y) = tile_id.decode();
+                format!("{}/{}/{}", z, x, y)
+            }).collect();
+            println!("  {} length={} count={} e.g. {}", content.hash, content.length, content.count, samples.join(", "));
+        }
+    }
+}
+
+/// The header's `num_tile_contents` next to the number of distinct (offset, length) pairs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentsCheck {
+    pub header: u64,
+    pub distinct: u64,
+}
+
+impl ContentsCheck {
+    pub fn is_consistent(&self) -> bool {
+        self.header == self.distinct
+    }
+}
+
+impl PMTiles {
+    /// Hashes the payload of every tile entry. Entries sharing an (offset, length) are
+    /// hashed only once.
+    pub fn hash_tiles(&self, algorithm: HashAlgorithm) -> io::Result<Vec<TileHash>> {
+        let mut hashes: HashMap<(usize, usize), String> = HashMap::new();
+        let mut tile_hashes = Vec::new();
+        for entry in self.tile_entries()? {
+            let hash = match hashes.get(&(entry.offset, entry.length)) {
+                Some(hash) => hash.clone(),
+                None => {
+                    let hash = algorithm.hash(self.tile_data(&entry)?);
+                    hashes.insert((entry.offset, entry.length), hash.clone());
+                    hash
+                },
+            };
+            tile_hashes.push(TileHash { tile_id: entry.tileid, run_length: entry.run_length, length: entry.length, hash });
+        }
+        Ok(tile_hashes)
+    }
+
+    /// Reports the `top` most duplicated payloads with up to `max_samples` TileIDs each.
+    pub fn duplicate_report(&self, algorithm: HashAlgorithm, top: usize, max_samples: usize) -> io::Result<DuplicateReport> {
+        let mut contents: HashMap<String, DuplicateContent> = HashMap::new();
+        let mut num_addressed_tiles = 0;
+        for tile_hash in self.hash_tiles(algorithm)? {
+            num_addressed_tiles += tile_hash.run_length as u64;
+            let content = contents.entry(tile_hash.hash.clone()).or_insert_with(|| DuplicateContent {
+                hash: tile_hash.hash,
+                length: tile_hash.length,
+                count: 0,
+                sample_tile_ids: Vec::new(),
+            });
+            content.count += tile_hash.run_length as u64;
+            let first = tile_hash.tile_id.value();
+            let samples = (first..first + tile_hash.run_length as u64)
+                .take(max_samples.saturating_sub(content.sample_tile_ids.len()));
+            content.sample_tile_ids.extend(samples.map(TileId::new));
+        }
+
+        let num_distinct_hashes = contents.len() as u64;
+        let mut duplicates: Vec<DuplicateContent> = contents.into_values().filter(|content| content.count > 1).collect();
+        // 件数の多い順、同数ならハッシュ順にして結果を安定させる
+        duplicates.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.hash.cmp(&b.hash)));
+        duplicates.truncate(top);
+
+        Ok(DuplicateReport { num_addressed_tiles, num_distinct_hashes, top: duplicates })
+    }
+
+    pub fn check_num_tile_contents(&self) -> io::Result<ContentsCheck> {
+        Ok(ContentsCheck { header: self.header.num_tile_contents, distinct: self.stats()?.num_tile_contents })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::header::Header;
+    use crate::pmtiles::testing::{TempPath, test_header, write_archive};
+    use crate::pmtiles::types::{Compression, TileType};
+
+    #[test]
+    fn hash_algorithms() {
+        assert_eq!(HashAlgorithm::Xxh3.hash(b""), "2d06800538d394c2");
+        assert_eq!(HashAlgorithm::Sha256.hash(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn report_duplicates() {
+        let temp_path = TempPath::new("hash");
+        let file_path = temp_path.as_str();
+
+        let header = Header { max_zoom: 2, ..test_header(TileType::MVT, Compression::None) };
+        // 0..=4はocean、5, 6はland、それ以外は個別
+        let tiles = (0..10u64).map(|i| {
+            let data = match i {
+                0..=4 => b"ocean".to_vec(),
+                5 | 6 => b"land".to_vec(),
+                _ => i.to_le_bytes().to_vec(),
+            };
+            (TileId::new(i), data)
+        });
+        write_archive(&temp_path, header, "{}", tiles);
+
+        let pmtiles = PMTiles::open(file_path).unwrap();
+        let report = pmtiles.duplicate_report(HashAlgorithm::Sha256, 10, 3).unwrap();
+        assert!(pmtiles.check_num_tile_contents().unwrap().is_consistent());
+
+        // ヘッダのnum_tile_contentsをずらす
+        let mut data = std::fs::read(file_path).unwrap();
+        data[0x58] += 1;
+        std::fs::write(file_path, &data).unwrap();
+        let check = PMTiles::open(file_path).unwrap().check_num_tile_contents().unwrap();
+
+        assert_eq!(report.num_addressed_tiles, 10);
+        assert_eq!(report.num_distinct_hashes, 5);
+        assert_eq!(report.top.len(), 2);
+        assert_eq!(report.top[0].hash, HashAlgorithm::Sha256.hash(b"ocean"));
+        assert_eq!(report.top[0].count, 5);
+        assert_eq!(report.top[0].sample_tile_ids, [TileId::new(0), TileId::new(1), TileId::new(2)]);
+        assert_eq!((report.top[1].count, report.top[1].length), (2, 4));
+        assert_eq!(check, ContentsCheck { header: 6, distinct: 5 });
+    }
+}
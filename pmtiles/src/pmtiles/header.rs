@@ -1,9 +1,11 @@
+use std::fmt;
 use std::io;
 
 use super::types::{Compression, TileType};
+use crate::tileid::MAX_ZOOM;
 
 const MAGIC_NUMBER: &[u8] = b"PMTiles";
-const HEADER_SIZE: usize = 127;
+pub const HEADER_SIZE: usize = 127;
 
 fn to_u64_le(bytes: &[u8]) -> u64 {
     // sliceの場合は一度try_intoで配列に変換する必要がある
@@ -18,7 +20,89 @@ fn to_lat_lon(bytes: &[u8; 8]) -> (f64, f64) {
     (lon, lat)
 }
 
-#[derive(Debug, Clone)]
+fn from_lat_lon(position: (f64, f64)) -> [u8; 8] {
+    // to_lat_lonの逆変換。E7の固定小数点に戻す
+    let lon = (position.0 * 10_000_000.0).round() as i32;
+    let lat = (position.1 * 10_000_000.0).round() as i32;
+    let mut bytes = [0u8; 8];
+    bytes[0..4].copy_from_slice(&lon.to_le_bytes());
+    bytes[4..8].copy_from_slice(&lat.to_le_bytes());
+    bytes
+}
+
+/// A byte range described by the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Header,
+    RootDirectory,
+    Metadata,
+    LeafDirectories,
+    TileData,
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A header inconsistency that makes the archive unreadable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderError {
+    UnsupportedVersion(u8),
+    SectionOutOfBounds { section: Section, offset: usize, length: usize, file_len: usize },
+    SectionsOverlap(Section, Section),
+    MaxZoomTooDeep(u8),
+    ZoomsInverted { min_zoom: u8, max_zoom: u8 },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::UnsupportedVersion(version) => write!(f, "Unsupported PMTiles version: {}", version),
+            HeaderError::SectionOutOfBounds { section, offset, length, file_len } =>
+                write!(f, "{} {}+{} is outside of the archive ({} bytes)", section, offset, length, file_len),
+            HeaderError::SectionsOverlap(a, b) => write!(f, "{} overlaps {}", a, b),
+            HeaderError::MaxZoomTooDeep(max_zoom) => write!(f, "max_zoom {} is beyond {}", max_zoom, MAX_ZOOM),
+            HeaderError::ZoomsInverted { min_zoom, max_zoom } =>
+                write!(f, "min_zoom {} is greater than max_zoom {}", min_zoom, max_zoom),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+impl From<HeaderError> for io::Error {
+    fn from(error: HeaderError) -> Self {
+        let kind = match error {
+            HeaderError::SectionOutOfBounds { .. } => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+/// A header value that is out of range but does not prevent reading tiles.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderWarning {
+    CenterZoomOutOfRange(u8),
+    PositionOutOfRange((f64, f64)),
+    EmptyBounds,
+    CenterOutsideBounds,
+}
+
+impl fmt::Display for HeaderWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderWarning::CenterZoomOutOfRange(zoom) => write!(f, "center_zoom {} is outside of min_zoom..=max_zoom", zoom),
+            HeaderWarning::PositionOutOfRange((lon, lat)) => write!(f, "Position ({}, {}) is outside of ±180/±90", lon, lat),
+            HeaderWarning::EmptyBounds => write!(f, "min_position is not below max_position"),
+            HeaderWarning::CenterOutsideBounds => write!(f, "center_position is outside of the bounds"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct Header {
     pub version: u8,
     pub root_dir_offset: usize,
@@ -53,9 +137,16 @@ impl Header {
             ));
         }
 
+        if super::v2::is_v2(data) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "PMTiles v2 archive: open it with PMTilesV2 or convert it with v2::upgrade_to_v3"
+            ));
+        }
+
         let magic_number = &data[0x00..0x07];
         match str::from_utf8(magic_number) {
-            Ok(s) => println!("Magic Number:{}",s),
+            Ok(s) => log::trace!("Magic Number:{}",s),
             Err(e) => return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!("Invalid magic number: {}", e)
@@ -69,6 +160,12 @@ impl Header {
         }
 
         let version = data[0x07];
+        if version != 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported PMTiles version: {}", version)
+            ));
+        }
         let root_dir_offset  = to_u64_le(&data[0x08..0x10]) as usize;
         let root_dir_length = to_u64_le(&data[0x10..0x18]) as usize;
         let metadata_offset = to_u64_le(&data[0x18..0x20]) as usize;
@@ -81,9 +178,10 @@ impl Header {
         let num_tile_entries: u64 = to_u64_le(&data[0x50..0x58]);
         let num_tile_contents: u64 = to_u64_le(&data[0x58..0x60]);
         let clustered : u8 = data[0x60];
-        let internal_compression : Compression = data[0x61].try_into().expect("invalid compression value");
-        let tile_compression : Compression = data[0x62].try_into().expect("invalid compression value");
-        let tile_type : TileType = data[0x63].try_into().expect("invalid tile type value");
+        let invalid_data = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        let internal_compression : Compression = data[0x61].try_into().map_err(invalid_data)?;
+        let tile_compression : Compression = data[0x62].try_into().map_err(invalid_data)?;
+        let tile_type : TileType = data[0x63].try_into().map_err(invalid_data)?;
         let min_zoom : u8 = data[0x64];
         let max_zoom : u8 = data[0x65];
         let min_position  = to_lat_lon(&data[0x66..0x6E].try_into().expect("slice with incorrect length"));
@@ -92,32 +190,118 @@ impl Header {
         let center_position  = to_lat_lon(&data[0x77..0x7F].try_into().expect("slice with incorrect length"));
 
         Ok(Header {
-            version: version,
-            root_dir_offset: root_dir_offset,
-            root_dir_length: root_dir_length,
-            metadata_offset: metadata_offset,
-            metadata_length: metadata_length,
-            leaf_dirs_offset: leaf_dirs_offset,
-            leaf_dirs_length: leaf_dirs_length,
-            tile_data_offset: tile_data_offset,
-            tile_data_length: tile_data_length,
-            num_addressed_tiles: num_addressed_tiles,
-            num_tile_entries: num_tile_entries,
-            num_tile_contents: num_tile_contents,
-            clustered: clustered,
-            internal_compression: internal_compression,
-            tile_compression: tile_compression,
-            tile_type: tile_type,
-            min_zoom: min_zoom,
-            max_zoom: max_zoom,
-            min_position: min_position,
-            max_position: max_position,
-            center_zoom: center_zoom,
-            center_position: center_position, 
+            version,
+            root_dir_offset,
+            root_dir_length,
+            metadata_offset,
+            metadata_length,
+            leaf_dirs_offset,
+            leaf_dirs_length,
+            tile_data_offset,
+            tile_data_length,
+            num_addressed_tiles,
+            num_tile_entries,
+            num_tile_contents,
+            clustered,
+            internal_compression,
+            tile_compression,
+            tile_type,
+            min_zoom,
+            max_zoom,
+            min_position,
+            max_position,
+            center_zoom,
+            center_position,
         })
         
     }
 
+    /// Serializes the header into its 127-byte little-endian representation.
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut data = [0u8; HEADER_SIZE];
+        data[0x00..0x07].copy_from_slice(MAGIC_NUMBER);
+        data[0x07] = self.version;
+        data[0x08..0x10].copy_from_slice(&(self.root_dir_offset as u64).to_le_bytes());
+        data[0x10..0x18].copy_from_slice(&(self.root_dir_length as u64).to_le_bytes());
+        data[0x18..0x20].copy_from_slice(&(self.metadata_offset as u64).to_le_bytes());
+        data[0x20..0x28].copy_from_slice(&(self.metadata_length as u64).to_le_bytes());
+        data[0x28..0x30].copy_from_slice(&(self.leaf_dirs_offset as u64).to_le_bytes());
+        data[0x30..0x38].copy_from_slice(&(self.leaf_dirs_length as u64).to_le_bytes());
+        data[0x38..0x40].copy_from_slice(&(self.tile_data_offset as u64).to_le_bytes());
+        data[0x40..0x48].copy_from_slice(&(self.tile_data_length as u64).to_le_bytes());
+        data[0x48..0x50].copy_from_slice(&self.num_addressed_tiles.to_le_bytes());
+        data[0x50..0x58].copy_from_slice(&self.num_tile_entries.to_le_bytes());
+        data[0x58..0x60].copy_from_slice(&self.num_tile_contents.to_le_bytes());
+        data[0x60] = self.clustered;
+        data[0x61] = self.internal_compression as u8;
+        data[0x62] = self.tile_compression as u8;
+        data[0x63] = self.tile_type as u8;
+        data[0x64] = self.min_zoom;
+        data[0x65] = self.max_zoom;
+        data[0x66..0x6E].copy_from_slice(&from_lat_lon(self.min_position));
+        data[0x6E..0x76].copy_from_slice(&from_lat_lon(self.max_position));
+        data[0x76] = self.center_zoom;
+        data[0x77..0x7F].copy_from_slice(&from_lat_lon(self.center_position));
+        data
+    }
+
+    /// Checks the header against an archive of `file_len` bytes. Problems that make
+    /// sections unreadable are errors; out-of-range bounds, center and center zoom are
+    /// returned as warnings. Bounds and center that are all zero count as unset and are not checked.
+    pub fn validate(&self, file_len: usize) -> Result<Vec<HeaderWarning>, HeaderError> {
+        if self.version != 3 {
+            return Err(HeaderError::UnsupportedVersion(self.version));
+        }
+        let sections = [
+            (Section::Header, 0, HEADER_SIZE),
+            (Section::RootDirectory, self.root_dir_offset, self.root_dir_length),
+            (Section::Metadata, self.metadata_offset, self.metadata_length),
+            (Section::LeafDirectories, self.leaf_dirs_offset, self.leaf_dirs_length),
+            (Section::TileData, self.tile_data_offset, self.tile_data_length),
+        ];
+        for &(section, offset, length) in &sections {
+            if offset.checked_add(length).is_none_or(|end| end > file_len) {
+                return Err(HeaderError::SectionOutOfBounds { section, offset, length, file_len });
+            }
+        }
+        // 長さ0のセクションは重なりを気にしない
+        for (i, &(a, a_offset, a_length)) in sections.iter().enumerate() {
+            for &(b, b_offset, b_length) in &sections[i + 1..] {
+                if a_length > 0 && b_length > 0 && a_offset < b_offset + b_length && b_offset < a_offset + a_length {
+                    return Err(HeaderError::SectionsOverlap(a, b));
+                }
+            }
+        }
+        if self.max_zoom > MAX_ZOOM {
+            return Err(HeaderError::MaxZoomTooDeep(self.max_zoom));
+        }
+        if self.min_zoom > self.max_zoom {
+            return Err(HeaderError::ZoomsInverted { min_zoom: self.min_zoom, max_zoom: self.max_zoom });
+        }
+
+        let mut warnings = Vec::new();
+        if !(self.min_zoom..=self.max_zoom).contains(&self.center_zoom) {
+            warnings.push(HeaderWarning::CenterZoomOutOfRange(self.center_zoom));
+        }
+        let in_range = |(lon, lat): (f64, f64)| (-180.0..=180.0).contains(&lon) && (-90.0..=90.0).contains(&lat);
+        for position in [self.min_position, self.max_position] {
+            if !in_range(position) {
+                warnings.push(HeaderWarning::PositionOutOfRange(position));
+            }
+        }
+        let (min, max) = (self.min_position, self.max_position);
+        // 全部0なら範囲が設定されていないだけなので警告しない
+        let unset = [min, max, self.center_position].iter().all(|&position| position == (0.0, 0.0));
+        if !unset {
+            if min.0 >= max.0 || min.1 >= max.1 {
+                warnings.push(HeaderWarning::EmptyBounds);
+            } else if !(min.0..=max.0).contains(&self.center_position.0) || !(min.1..=max.1).contains(&self.center_position.1) {
+                warnings.push(HeaderWarning::CenterOutsideBounds);
+            }
+        }
+        Ok(warnings)
+    }
+
     pub fn print_info(&self) {
         println!("PMTiles Header:");
         println!("  Version: {}", self.version);
@@ -142,6 +326,9 @@ impl Header {
 #[cfg(test)]
 mod tests {
     use super::*;
+    // proptestはwasm32では使えない
+    #[cfg(not(target_arch = "wasm32"))]
+    use proptest::prelude::*;
 
     const HEADER_DATA: [u8; 127] = [
         0x50, 0x4d, 0x54, 0x69, 0x6c, 0x65, 0x73, 0x03, 0x7f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -154,6 +341,8 @@ mod tests {
         0x3f, 0x5c, 0x00, 0x0b, 0x6b, 0x1b, 0x10, 0xa2, 0x25, 0xd3, 0x50, 0xbd, 0x92, 0xc2, 0x14,
     ];
 
+    // サンプルのタイルデータは4GiBを超えるので32bitのusizeに収まらない
+    #[cfg(target_pointer_width = "64")]
     #[test]
     fn parse_correct_header() {
         let header = Header::parse(&HEADER_DATA)
@@ -181,5 +370,92 @@ mod tests {
         assert_eq!(header.center_zoom, 16);
         assert_eq!(header.center_position, (135.601501, 34.8295869));
     }
-    
-}
\ No newline at end of file
+
+    #[test]
+    fn header_round_trip() {
+        let header = Header::parse(&HEADER_DATA)
+            .expect("parse header successful");
+        assert_eq!(header.to_bytes(), HEADER_DATA);
+
+        let reparsed = Header::parse(&header.to_bytes())
+            .expect("parse serialized header successful");
+        assert_eq!(reparsed.min_position, header.min_position);
+        assert_eq!(reparsed.max_position, header.max_position);
+        assert_eq!(reparsed.center_position, header.center_position);
+    }
+
+    #[test]
+    fn parse_rejects_invalid_types() {
+        let mut data = HEADER_DATA;
+        data[0x61] = 0xff;
+        assert_eq!(Header::parse(&data).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        data = HEADER_DATA;
+        data[0x63] = 0xff;
+        assert_eq!(Header::parse(&data).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    // サンプルのタイルデータは4GiBを超えるので32bitのusizeに収まらない
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn validate_header() {
+        let header = Header::parse(&HEADER_DATA).unwrap();
+        let file_len = header.tile_data_offset + header.tile_data_length;
+        assert_eq!(header.validate(file_len), Ok(vec![]));
+        assert_eq!(
+            header.validate(file_len - 1),
+            Err(HeaderError::SectionOutOfBounds { section: Section::TileData, offset: 5763734, length: 16898675093, file_len: file_len - 1 })
+        );
+
+        let mut overlapping = header.clone();
+        overlapping.metadata_offset = 100;
+        assert_eq!(overlapping.validate(file_len), Err(HeaderError::SectionsOverlap(Section::Header, Section::Metadata)));
+        let mut overlapping = header.clone();
+        overlapping.leaf_dirs_length += 1;
+        assert_eq!(overlapping.validate(file_len), Err(HeaderError::SectionsOverlap(Section::LeafDirectories, Section::TileData)));
+
+        let mut zooms = header.clone();
+        zooms.max_zoom = 32;
+        assert_eq!(zooms.validate(file_len), Err(HeaderError::MaxZoomTooDeep(32)));
+        zooms.max_zoom = 3;
+        assert_eq!(zooms.validate(file_len), Err(HeaderError::ZoomsInverted { min_zoom: 4, max_zoom: 3 }));
+        let error: io::Error = HeaderError::UnsupportedVersion(2).into();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut warned = header.clone();
+        warned.center_zoom = 17;
+        warned.center_position = (100.0, 35.0);
+        warned.max_position = (190.0, 46.0);
+        assert_eq!(warned.validate(file_len), Ok(vec![
+            HeaderWarning::CenterZoomOutOfRange(17),
+            HeaderWarning::PositionOutOfRange((190.0, 46.0)),
+            HeaderWarning::CenterOutsideBounds,
+        ]));
+        warned.min_position = (190.0, 0.0);
+        assert!(warned.validate(file_len).unwrap().contains(&HeaderWarning::EmptyBounds));
+    }
+
+    #[test]
+    fn unset_bounds_are_not_warned() {
+        let mut header = Header { version: 3, ..Default::default() };
+        assert_eq!(header.validate(HEADER_SIZE), Ok(vec![]));
+        header.max_position = (10.0, 0.0);
+        assert_eq!(header.validate(HEADER_SIZE), Ok(vec![HeaderWarning::EmptyBounds]));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    proptest! {
+        #[test]
+        fn parse_never_panics(data in prop::collection::vec(any::<u8>(), 0..256)) {
+            let _ = Header::parse(&data);
+        }
+
+        #[test]
+        fn bytes_round_trip(mut data in prop::collection::vec(any::<u8>(), HEADER_SIZE), types in (0u8..=4, 0u8..=4, 0u8..=5)) {
+            data[0x00..0x07].copy_from_slice(MAGIC_NUMBER);
+            data[0x07] = 3;
+            (data[0x61], data[0x62], data[0x63]) = types;
+            let header = Header::parse(&data).unwrap();
+            prop_assert_eq!(&header.to_bytes()[..], &data[..]);
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use std::fmt::Write;
+use std::io;
+use std::ops::Range;
+
+use super::{MAX_DIRECTORY_DEPTH, PMTiles};
+use super::compression::decompress;
+use super::directory::DirectoryEntry;
+use crate::protobufs::decode_varint;
+use crate::tileid::{MAX_TILE_ID_END, MAX_ZOOM, TileId};
+
+/// Header fields by (offset, length, name).
+const HEADER_FIELDS: [(usize, usize, &str); 23] = [
+    (0x00, 7, "magic_number"),
+    (0x07, 1, "version"),
+    (0x08, 8, "root_dir_offset"),
+    (0x10, 8, "root_dir_length"),
+    (0x18, 8, "metadata_offset"),
+    (0x20, 8, "metadata_length"),
+    (0x28, 8, "leaf_dirs_offset"),
+    (0x30, 8, "leaf_dirs_length"),
+    (0x38, 8, "tile_data_offset"),
+    (0x40, 8, "tile_data_length"),
+    (0x48, 8, "num_addressed_tiles"),
+    (0x50, 8, "num_tile_entries"),
+    (0x58, 8, "num_tile_contents"),
+    (0x60, 1, "clustered"),
+    (0x61, 1, "internal_compression"),
+    (0x62, 1, "tile_compression"),
+    (0x63, 1, "tile_type"),
+    (0x64, 1, "min_zoom"),
+    (0x65, 1, "max_zoom"),
+    (0x66, 8, "min_position"),
+    (0x6E, 8, "max_position"),
+    (0x76, 1, "center_zoom"),
+    (0x77, 8, "center_position"),
+];
+
+/// A labelled byte range; `start` and `end` are absolute positions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub start: usize,
+    pub end: usize,
+    pub label: String,
+    pub value: String,
+}
+
+/// Bytes starting at `base` together with the structures they belong to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inspection {
+    pub base: usize,
+    pub bytes: Vec<u8>,
+    pub annotations: Vec<Annotation>,
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+impl Inspection {
+    /// Hex dump with the annotations starting on each row listed to its right.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (row, chunk) in self.bytes.chunks(16).enumerate() {
+            let row_start = self.base + row * 16;
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let notes: Vec<String> = self.annotations.iter()
+                .filter(|a| a.start.max(self.base) >= row_start && a.start.max(self.base) < row_start + 16)
+                .map(|a| format!("{}={}", a.label, a.value))
+                .collect();
+            let _ = writeln!(text, "{:08x} | {:<47} | {}", row_start, hex.join(" "), notes.join(", "));
+        }
+        text
+    }
+
+    /// Standalone HTML page; each byte is coloured by its annotation and shows it on hover.
+    pub fn to_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>PMTiles inspection</title><style>\n\
+             body { font-family: monospace; }\n\
+             .a0 { background: #fde2e2; } .a1 { background: #e2f0fd; } .a2 { background: #e5fde2; } .a3 { background: #fdf6e2; }\n\
+             </style></head><body>\n<pre>\n"
+        );
+        for (row, chunk) in self.bytes.chunks(16).enumerate() {
+            let row_start = self.base + row * 16;
+            let _ = write!(html, "{:08x} | ", row_start);
+            for (i, byte) in chunk.iter().enumerate() {
+                let position = row_start + i;
+                match self.annotations.iter().position(|a| a.start <= position && position < a.end) {
+                    Some(index) => {
+                        let a = &self.annotations[index];
+                        let _ = write!(html, "<span class=\"a{}\" title=\"{}\">{:02x}</span> ",
+                            index % 4, escape_html(&format!("{}={}", a.label, a.value)), byte);
+                    },
+                    None => { let _ = write!(html, "{:02x} ", byte); },
+                }
+            }
+            html.push('\n');
+        }
+        html.push_str("</pre>\n<table>\n<tr><th>start</th><th>end</th><th>structure</th><th>value</th></tr>\n");
+        for a in &self.annotations {
+            let _ = writeln!(html, "<tr><td>0x{:x}</td><td>0x{:x}</td><td>{}</td><td>{}</td></tr>",
+                a.start, a.end, escape_html(&a.label), escape_html(&a.value));
+        }
+        html.push_str("</table>\n</body></html>\n");
+        html
+    }
+}
+
+/// Annotates an uncompressed directory: the entry count and each value of the tile ID,
+/// run length, length and offset columns, with the decoded meaning of each varint.
+pub fn inspect_directory(data: &[u8]) -> io::Result<Inspection> {
+    let to_io_error = |e: &str| io::Error::new(io::ErrorKind::InvalidData, e.to_string());
+    let mut annotations = Vec::new();
+    let mut position = 0;
+    let mut read = |label: String, describe: &mut dyn FnMut(u64) -> String| -> io::Result<u64> {
+        let (value, size) = decode_varint(&data[position.min(data.len())..]).map_err(to_io_error)?;
+        annotations.push(Annotation { start: position, end: position + size, label, value: describe(value) });
+        position += size;
+        Ok(value)
+    };
+
+    let count = read("num_entries".to_string(), &mut |value| value.to_string())?;
+    // 壊れたデータでもパニックせず、溢れた値はそのまま注釈に書く
+    let mut tile_id = Some(0u64);
+    let mut lengths = Vec::new();
+    for i in 0..count {
+        read(format!("tile_id_delta[{}]", i), &mut |delta| {
+            tile_id = tile_id.and_then(|tile_id| tile_id.checked_add(delta));
+            match tile_id {
+                Some(tile_id) if tile_id < MAX_TILE_ID_END => {
+                    let (z, x, y) = TileId::new(tile_id).decode();
+                    format!("+{} -> TileID {} ({}/{}/{})", delta, tile_id, z, x, y)
+                },
+                Some(tile_id) => format!("+{} -> TileID {} (beyond zoom {})", delta, tile_id, MAX_ZOOM),
+                None => format!("+{} -> TileID overflows 64 bits", delta),
+            }
+        })?;
+    }
+    for i in 0..count {
+        read(format!("run_length[{}]", i), &mut |run_length| match run_length {
+            0 => "0 (leaf directory)".to_string(),
+            _ => run_length.to_string(),
+        })?;
+    }
+    for i in 0..count {
+        lengths.push(read(format!("length[{}]", i), &mut |length| length.to_string())?);
+    }
+    let mut next_offset = Some(0u64);
+    for (i, &length) in lengths.iter().enumerate() {
+        read(format!("offset[{}]", i), &mut |raw| {
+            let offset = if raw == 0 && i > 0 { next_offset } else { Some(raw.saturating_sub(1)) };
+            next_offset = offset.and_then(|offset| offset.checked_add(length));
+            match (raw, offset) {
+                (0, Some(offset)) if i > 0 => format!("0 (contiguous) -> {}", offset),
+                (0, None) if i > 0 => "0 (contiguous) -> overflows 64 bits".to_string(),
+                (_, offset) => format!("{} -> {}", raw, offset.unwrap_or_default()),
+            }
+        })?;
+    }
+
+    Ok(Inspection { base: 0, bytes: data.to_vec(), annotations })
+}
+
+impl PMTiles {
+    /// Annotates `length` bytes of the archive starting at `start` with the header fields,
+    /// sections and tiles they belong to.
+    pub fn inspect(&self, start: usize, length: usize) -> io::Result<Inspection> {
+        let bytes = self.slice(start, length)?.to_vec();
+        // sliceが範囲を確認済みなので溢れない
+        let end = start + length;
+        let overlaps = |a_start: usize, a_end: usize| a_start < end && start < a_end;
+
+        let mut annotations = Vec::new();
+        let header_values = self.header_field_values();
+        for ((offset, size, name), value) in HEADER_FIELDS.iter().zip(header_values) {
+            if overlaps(*offset, offset + size) {
+                annotations.push(Annotation { start: *offset, end: offset + size, label: name.to_string(), value });
+            }
+        }
+
+        let header = &self.header;
+        let sections = [
+            ("root directory", header.root_dir_offset, header.root_dir_length, header.internal_compression),
+            ("metadata", header.metadata_offset, header.metadata_length, header.internal_compression),
+            ("leaf directories", header.leaf_dirs_offset, header.leaf_dirs_length, header.internal_compression),
+        ];
+        for (name, offset, size, compression) in sections {
+            if size > 0 && overlaps(offset, offset.saturating_add(size)) {
+                annotations.push(Annotation {
+                    start: offset, end: offset.saturating_add(size),
+                    label: name.to_string(), value: format!("{} bytes, {}", size, compression),
+                });
+            }
+        }
+
+        let tile_start = |entry: &DirectoryEntry| header.tile_data_offset.saturating_add(entry.offset);
+        if overlaps(header.tile_data_offset, header.tile_data_offset.saturating_add(header.tile_data_length)) {
+            let mut tiles = Vec::new();
+            let mut data_end = 0;
+            self.collect_overlapping_tiles(self.root_directory.entries.iter().cloned(), start..end, 1, &mut data_end, &mut tiles)?;
+            // 重複したコンテンツは最初のタイルだけを表示する
+            tiles.sort_by_key(|entry| (entry.offset, entry.tileid.value()));
+            tiles.dedup_by_key(|entry| entry.offset);
+            for entry in tiles {
+                let (z, x, y) = entry.tileid.decode();
+                let tile_start = tile_start(&entry);
+                annotations.push(Annotation {
+                    start: tile_start, end: tile_start.saturating_add(entry.length),
+                    label: format!("tile {}/{}/{}", z, x, y),
+                    value: format!("{} bytes, {}", entry.length, header.tile_compression),
+                });
+            }
+        }
+
+        Ok(Inspection { base: start, bytes, annotations })
+    }
+
+    /// Collects the tile entries whose data overlaps `range`, reading leaves through the
+    /// `get_tile` cache. In a clustered archive new tile data only ever follows `data_end`,
+    /// so the walk stops at the first entry past the range; later entries can only repeat
+    /// contents already seen.
+    fn collect_overlapping_tiles(
+        &self,
+        directory: impl Iterator<Item = DirectoryEntry>,
+        range: Range<usize>,
+        depth: usize,
+        data_end: &mut usize,
+        tiles: &mut Vec<DirectoryEntry>,
+    ) -> io::Result<bool> {
+        if depth > MAX_DIRECTORY_DEPTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Leaf directories are nested too deeply"));
+        }
+        for entry in directory {
+            if self.header.clustered == 1 && *data_end >= range.end {
+                return Ok(true);
+            }
+            if entry.run_length == 0 {
+                let leaf = self.cached_leaf(&entry)?;
+                if self.collect_overlapping_tiles(leaf.entries(), range.clone(), depth + 1, data_end, tiles)? {
+                    return Ok(true);
+                }
+                continue;
+            }
+            let tile_start = self.header.tile_data_offset.saturating_add(entry.offset);
+            let tile_end = tile_start.saturating_add(entry.length);
+            *data_end = (*data_end).max(tile_end);
+            if tile_start < range.end && range.start < tile_end {
+                tiles.push(entry);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Annotates the root directory as stored in the archive, after removing its internal compression.
+    pub fn inspect_root_directory(&self) -> io::Result<Inspection> {
+        let data = self.slice(self.header.root_dir_offset, self.header.root_dir_length)?;
+        inspect_directory(&decompress(data, self.header.internal_compression)?)
+    }
+
+    fn header_field_values(&self) -> Vec<String> {
+        let h = &self.header;
+        let position = |p: (f64, f64)| format!("({:.7}, {:.7})", p.0, p.1);
+        vec![
+            "PMTiles".to_string(), h.version.to_string(),
+            h.root_dir_offset.to_string(), h.root_dir_length.to_string(),
+            h.metadata_offset.to_string(), h.metadata_length.to_string(),
+            h.leaf_dirs_offset.to_string(), h.leaf_dirs_length.to_string(),
+            h.tile_data_offset.to_string(), h.tile_data_length.to_string(),
+            h.num_addressed_tiles.to_string(), h.num_tile_entries.to_string(), h.num_tile_contents.to_string(),
+            h.clustered.to_string(), h.internal_compression.to_string(), h.tile_compression.to_string(),
+            h.tile_type.to_string(), h.min_zoom.to_string(), h.max_zoom.to_string(),
+            position(h.min_position), position(h.max_position), h.center_zoom.to_string(),
+            position(h.center_position),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::header::{Header, HEADER_SIZE};
+    use crate::pmtiles::testing::{archive_bytes, open_archive, test_header};
+    use crate::pmtiles::types::{Compression, TileType};
+
+    #[test]
+    fn inspect_directory_columns() {
+        let data = [0x02, 0x01, 0x02, 0x01, 0x00, 10, 20, 11, 0];
+        let inspection = inspect_directory(&data).unwrap();
+        assert_eq!(inspection.annotations.len(), 9);
+        assert_eq!(inspection.annotations[2].value, "+2 -> TileID 3 (1/1/1)");
+        assert_eq!(inspection.annotations[4].value, "0 (leaf directory)");
+        assert_eq!(inspection.annotations[8].value, "0 (contiguous) -> 20");
+        assert!(inspection.to_text().starts_with("00000000 | 02 01 02 01 00 0a 14 0b 00"));
+    }
+
+    #[test]
+    fn inspect_overflowing_directory() {
+        let max = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        let data = [&[0x02][..], &max, &[0x05, 0x01, 0x01, 10, 10], &max, &[0x00]].concat();
+        let inspection = inspect_directory(&data).unwrap();
+        assert_eq!(inspection.annotations.len(), 9);
+        assert_eq!(inspection.annotations[1].value, format!("+{} -> TileID {} (beyond zoom 31)", u64::MAX, u64::MAX));
+        assert_eq!(inspection.annotations[2].value, "+5 -> TileID overflows 64 bits");
+        assert_eq!(inspection.annotations[8].value, "0 (contiguous) -> overflows 64 bits");
+    }
+
+    #[test]
+    fn inspect_header_and_tiles() {
+        let tiles: [(TileId, &[u8]); 2] = [(TileId::new(0), b"<zero>"), (TileId::new(1), b"one")];
+        let pmtiles = open_archive(test_header(TileType::MVT, Compression::None), "{}", tiles);
+
+        let inspection = pmtiles.inspect(0, HEADER_SIZE).unwrap();
+        assert_eq!(inspection.annotations.len(), 23);
+        assert!(inspection.to_text().contains("root_dir_offset=127"));
+        assert!(inspection.to_html().contains("title=\"version=3\""));
+
+        let tile_data_offset = pmtiles.header.tile_data_offset;
+        let inspection = pmtiles.inspect(tile_data_offset, 9).unwrap();
+        let labels: Vec<&str> = inspection.annotations.iter().map(|a| a.label.as_str()).collect();
+        assert_eq!(labels, ["tile 0/0/0", "tile 1/0/0"]);
+        assert!(inspection.to_html().contains("tile 1/0/0=3 bytes, None"));
+
+        let root = pmtiles.inspect_root_directory().unwrap();
+        assert_eq!(root.annotations[0].value, "2");
+    }
+
+    #[test]
+    fn inspect_reads_leaves_only_up_to_the_region() {
+        let tiles = (0..20_000u64).map(|i| (TileId::new(i), i.to_le_bytes()));
+        let mut data = archive_bytes(test_header(TileType::MVT, Compression::None), "{}", tiles);
+        let pmtiles = PMTiles::from_bytes(data.clone()).unwrap();
+        assert_eq!(pmtiles.header.clustered, 1);
+        // 最後のリーフを壊しておく
+        let last_leaf = pmtiles.root_directory.entries.last().unwrap();
+        let leaf_start = pmtiles.header.leaf_dirs_offset + last_leaf.offset;
+        data[leaf_start..leaf_start + last_leaf.length].fill(0xff);
+        let pmtiles = PMTiles::from_bytes(data).unwrap();
+
+        let tile_data_offset = pmtiles.header.tile_data_offset;
+        let inspection = pmtiles.inspect(tile_data_offset + 4, 8).unwrap();
+        let labels: Vec<&str> = inspection.annotations.iter().map(|a| a.label.as_str()).collect();
+        assert_eq!(labels, ["tile 0/0/0", "tile 1/0/0"]);
+
+        let tile_data_end = tile_data_offset + pmtiles.header.tile_data_length;
+        assert!(pmtiles.inspect(tile_data_end - 8, 8).is_err());
+    }
+
+    #[test]
+    fn inspect_stored_root_directory() {
+        // 2番目のオフセットを連続(0)ではなく明示的に書いた、正規形でないディレクトリ
+        let root_directory = [0x02, 0x00, 0x01, 0x01, 0x01, 0x03, 0x03, 0x01, 0x04];
+        let header = Header {
+            version: 3,
+            root_dir_offset: HEADER_SIZE,
+            root_dir_length: root_directory.len(),
+            metadata_offset: HEADER_SIZE + root_directory.len(),
+            metadata_length: 2,
+            leaf_dirs_offset: HEADER_SIZE + root_directory.len() + 2,
+            tile_data_offset: HEADER_SIZE + root_directory.len() + 2,
+            tile_data_length: 6,
+            internal_compression: Compression::None,
+            tile_compression: Compression::None,
+            tile_type: TileType::MVT,
+            ..Default::default()
+        };
+        let data = [&header.to_bytes()[..], &root_directory, b"{}", b"onetwo"].concat();
+        let pmtiles = PMTiles::from_bytes(data).unwrap();
+        assert_eq!(pmtiles.get_tile(0, 0, 0).unwrap(), Some(&b"one"[..]));
+
+        let root = pmtiles.inspect_root_directory().unwrap();
+        assert_eq!(root.bytes, root_directory);
+        assert_eq!(root.annotations[8].value, "4 -> 3");
+    }
+}
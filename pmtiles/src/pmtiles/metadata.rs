@@ -1,7 +1,7 @@
-use std::io::{self, Read};
+use std::io;
 
-use flate2::read::GzDecoder;
-use super::types::TileType;
+use super::compression::{compress, decompress};
+use super::types::{Compression, TileType};
 use serde_json::Value;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -11,16 +11,13 @@ pub struct Metadata {
 
 #[allow(unused)]
 impl Metadata {
-    pub fn parse_compressed(data: &[u8], tile_type: TileType) -> io::Result<Self> {
-        let mut decoder = GzDecoder::new(data);
-        let mut metadata_decoded = Vec::new();
-        decoder.read_to_end(&mut metadata_decoded)?;
-
+    pub fn parse_compressed(data: &[u8], compression: Compression, tile_type: TileType) -> io::Result<Self> {
+        let metadata_decoded = decompress(data, compression)?;
         Self::parse(metadata_decoded, tile_type)
     }
 
     pub fn parse(data: Vec<u8>, tile_type: TileType) -> io::Result<Self> {
-        let metadata_str = String::from_utf8(data).expect("Metadata is valid UTF-8 string");
+        let metadata_str = String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
         if tile_type != TileType::MVT {
             return Ok(Metadata {json: metadata_str});
@@ -33,17 +30,31 @@ impl Metadata {
         let _type = &v["type"];
         let version = &v["version"];
         
-        println!("name: {}", name);
-        println!("description: {}", description);
-        println!("attribution: {}", attribution);
-        println!("type: {}", _type);
-        println!("version: {}", version);
+        log::debug!("name: {}", name);
+        log::debug!("description: {}", description);
+        log::debug!("attribution: {}", attribution);
+        log::debug!("type: {}", _type);
+        log::debug!("version: {}", version);
 
         //parse_optional(&v);
         
         Ok(Metadata {json: metadata_str})
     }
 
+    /// Creates metadata from a JSON string, rejecting anything that is not valid JSON.
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        serde_json::from_str::<Value>(json)?;
+        Ok(Metadata {json: json.to_string()})
+    }
+
+    pub fn json(&self) -> &str {
+        &self.json
+    }
+
+    pub fn to_compressed(&self, compression: Compression) -> io::Result<Vec<u8>> {
+        compress(self.json.as_bytes(), compression)
+    }
+
     pub fn print_info(&self) {
         println!("Metadata:\n{}", self.json);
     }
@@ -52,19 +63,19 @@ impl Metadata {
 #[allow(unused)]
 fn parse_optional(value: &Value) {
     let format = &value["format"];
-    println!("format: {}", format);
+    log::debug!("format: {}", format);
     let generator = &value["generator"];
-    println!("generator: {}", generator);
+    log::debug!("generator: {}", generator);
     let generator_options = &value["generator_options"];
-    println!("generator_options: {}", generator_options);
+    log::debug!("generator_options: {}", generator_options);
     let maxzoom = &value["maxzoom"];
-    println!("maxzoom: {}", maxzoom);
+    log::debug!("maxzoom: {}", maxzoom);
     let minzoom = &value["minzoom"];
-    println!("minzoom: {}", minzoom);
+    log::debug!("minzoom: {}", minzoom);
     let tilestats = &value["tilestats"];
     let layer_count = &tilestats["layerCount"];
     let layers = &tilestats["layers"];
-    println!("tilestats layerCount: {}", layer_count);
+    log::debug!("tilestats layerCount: {}", layer_count);
     match layers.as_array(){
         Some(array) => {
             array.iter().for_each(|layer| {
@@ -72,7 +83,7 @@ fn parse_optional(value: &Value) {
             });
         },
         None => {
-            println!("tilestats layers is not an array");
+            log::warn!("tilestats layers is not an array");
         }
     }
 
@@ -82,11 +93,11 @@ fn print_key_values(value: &Value) {
         match value.as_object() {
             Some(map) => {
                 map.iter().for_each(|(key, value)| {
-                    println!("Metadata Key: {}, Value: {}", key, value);
+                    log::debug!("Metadata Key: {}, Value: {}", key, value);
                 })
             },
             None => {
-                println!("Metadata is not a JSON object");
+                log::warn!("Metadata is not a JSON object");
             }
         };
     }
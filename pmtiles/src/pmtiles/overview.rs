@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::rc::Rc;
+
+use image::{RgbaImage, imageops};
+
+use super::PMTiles;
+use super::compression::{compress, decompress};
+use super::directory::DirectoryEntry;
+use super::header::Header;
+use super::raster::{RasterTile, decode_tile, encode_tile};
+use super::writer::write_to_file;
+use crate::tileid::TileId;
+
+/// Decoded source tiles kept around for reuse; runs usually repeat the same few contents.
+const DECODED_CACHE_SIZE: usize = 64;
+
+/// Walks the quadtree depth first, so only the children of the parents on the current
+/// path are held decoded at any time.
+struct OverviewBuilder<'a> {
+    input: &'a PMTiles,
+    /// Entries of the source zoom, in TileID order.
+    source: Vec<DirectoryEntry>,
+    source_zoom: u8,
+    min_zoom: u8,
+    tile_size: u32,
+    decoded: HashMap<(usize, usize), Rc<RgbaImage>>,
+    overviews: BTreeMap<u64, Vec<u8>>,
+}
+
+impl OverviewBuilder<'_> {
+    /// TileIDs of the source zoom below `tile`. Hilbert order keeps them contiguous.
+    fn source_range(&self, tile: TileId, z: u8) -> (u64, u64) {
+        let descendants = 4u64.pow((self.source_zoom - z) as u32);
+        let index = tile.value() - TileId::encode(z, 0, 0).value();
+        let start = TileId::encode(self.source_zoom, 0, 0).value() + index * descendants;
+        (start, start + descendants)
+    }
+
+    /// The last source entry starting before `end`, i.e. the only one that can reach into `start..end`.
+    fn last_entry_before(&self, start: u64, end: u64) -> Option<&DirectoryEntry> {
+        let index = self.source.partition_point(|entry| entry.tileid.value() < end);
+        let entry = self.source.get(index.checked_sub(1)?)?;
+        (entry.tileid.value() + entry.run_length as u64 > start).then_some(entry)
+    }
+
+    fn source_image(&mut self, entry: &DirectoryEntry) -> io::Result<Rc<RgbaImage>> {
+        let key = (entry.offset, entry.length);
+        if let Some(image) = self.decoded.get(&key) {
+            return Ok(image.clone());
+        }
+        let data = decompress(self.input.tile_data(entry)?, self.input.header.tile_compression)?;
+        let tile = decode_tile(&data, self.input.header.tile_type)?;
+        let image = RgbaImage::from_raw(tile.width, tile.height, tile.pixels)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Decoded tile has an invalid buffer"))?;
+        if self.decoded.len() >= DECODED_CACHE_SIZE {
+            self.decoded.clear();
+        }
+        let image = Rc::new(image);
+        self.decoded.insert(key, image.clone());
+        Ok(image)
+    }
+
+    /// Returns the image of `tile`, building (and storing) overview tiles on the way.
+    /// Zooms above `min_zoom` are only walked through.
+    fn visit(&mut self, tile: TileId) -> io::Result<Option<Rc<RgbaImage>>> {
+        let (z, _, _) = tile.decode();
+        let (start, end) = self.source_range(tile, z);
+        let Some(entry) = self.last_entry_before(start, end).cloned() else {
+            return Ok(None);
+        };
+        if z == self.source_zoom {
+            return self.source_image(&entry).map(Some);
+        }
+
+        let tile_size = self.tile_size;
+        let mut canvas = (z >= self.min_zoom).then(|| RgbaImage::new(tile_size * 2, tile_size * 2));
+        for (i, child) in tile.children().iter().enumerate() {
+            let Some(image) = self.visit(*child)? else {
+                continue;
+            };
+            if let Some(canvas) = &mut canvas {
+                let (dx, dy) = ((i as u32 % 2) * tile_size, (i as u32 / 2) * tile_size);
+                if image.dimensions() == (tile_size, tile_size) {
+                    imageops::replace(canvas, image.as_ref(), dx as i64, dy as i64);
+                } else {
+                    let image = imageops::resize(image.as_ref(), tile_size, tile_size, imageops::FilterType::Triangle);
+                    imageops::replace(canvas, &image, dx as i64, dy as i64);
+                }
+            }
+        }
+        let Some(canvas) = canvas else {
+            return Ok(None);
+        };
+        let image = imageops::resize(&canvas, tile_size, tile_size, imageops::FilterType::Triangle);
+
+        let raster = RasterTile { width: tile_size, height: tile_size, pixels: image.as_raw().clone() };
+        let header = &self.input.header;
+        self.overviews.insert(tile.value(), compress(&encode_tile(&raster, header.tile_type)?, header.tile_compression)?);
+        Ok(Some(Rc::new(image)))
+    }
+}
+
+/// Writes a copy of `input_path` with overview zooms from `min_zoom` up to the input's
+/// `min_zoom`. Each parent tile is composed from its four children and downsampled to the
+/// size of the input tiles (typically 256 or 512 px); missing children stay transparent.
+pub fn build_overviews(input_path: &str, output_path: &str, min_zoom: u8) -> io::Result<Header> {
+    let input = PMTiles::open(input_path)?;
+    let source_zoom = input.header.min_zoom;
+    if min_zoom >= source_zoom {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The archive already starts at zoom {}", source_zoom)
+        ));
+    }
+    let entries = input.tile_entries()?;
+
+    // TileIDはズーム順に並ぶので、最小ズームのタイルは先頭にまとまっている
+    let source: Vec<DirectoryEntry> = entries.iter()
+        .take_while(|entry| entry.tileid.decode().0 <= source_zoom)
+        .filter(|entry| entry.tileid.decode().0 == source_zoom)
+        .cloned()
+        .collect();
+    let first = source.first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No tiles at zoom {}", source_zoom)))?;
+    let first = decode_tile(&decompress(input.tile_data(first)?, input.header.tile_compression)?, input.header.tile_type)?;
+
+    let mut builder = OverviewBuilder {
+        input: &input,
+        source,
+        source_zoom,
+        min_zoom,
+        tile_size: first.width.max(first.height),
+        decoded: HashMap::new(),
+        overviews: BTreeMap::new(),
+    };
+    builder.visit(TileId::new(0))?;
+    let overviews = builder.overviews;
+    log::debug!("Built {} overview tiles", overviews.len());
+
+    let mut header = input.header.clone();
+    header.min_zoom = min_zoom;
+    write_to_file(output_path, header, &input.metadata, |writer| {
+        for (tile_id, data) in &overviews {
+            writer.add_tile(TileId::new(*tile_id), data)?;
+        }
+        for entry in &entries {
+            let data = input.tile_data(entry)?;
+            for i in 0..entry.run_length as u64 {
+                writer.add_tile(TileId::new(entry.tileid.value() + i), data)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use crate::pmtiles::testing::{TempPath, archive_bytes, test_header};
+    use crate::pmtiles::types::{Compression, TileType};
+
+    #[test]
+    fn build_overviews_from_z2() {
+        let (input, output) = (TempPath::new("overview_in"), TempPath::new("overview_out"));
+        let header = Header { min_zoom: 2, max_zoom: 2, ..test_header(TileType::PNG, Compression::None) };
+        // 西半分は赤、東半分は青。(3,3)は欠けたままにする
+        let mut tiles = Vec::new();
+        for y in 0..4 {
+            for x in 0..4 {
+                if (x, y) != (3, 3) {
+                    let color = if x < 2 { [255, 0, 0, 255] } else { [0, 0, 255, 255] };
+                    let tile = RasterTile { width: 4, height: 4, pixels: color.repeat(16) };
+                    tiles.push((TileId::encode(2, x, y), encode_tile(&tile, TileType::PNG).unwrap()));
+                }
+            }
+        }
+        tiles.sort_by_key(|(tile_id, _)| tile_id.value());
+        std::fs::write(&input, archive_bytes(header, "{}", tiles)).unwrap();
+
+        assert_eq!(build_overviews(input.as_str(), output.as_str(), 2).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let header = build_overviews(input.as_str(), output.as_str(), 0).unwrap();
+        let pmtiles = PMTiles::open(output.as_str()).unwrap();
+
+        assert_eq!((header.min_zoom, header.max_zoom), (0, 2));
+        assert_eq!(header.num_addressed_tiles, 1 + 4 + 15);
+        assert_eq!(pmtiles.get_raster_tile(2, 0, 0).unwrap().unwrap().pixels[0..4], [255, 0, 0, 255]);
+
+        let z1 = pmtiles.get_raster_tile(1, 1, 1).unwrap().unwrap();
+        let z1 = RgbaImage::from_raw(z1.width, z1.height, z1.pixels).unwrap();
+        assert_eq!(z1.dimensions(), (4, 4));
+        assert_eq!(z1.get_pixel(0, 0), &Rgba([0, 0, 255, 255]));
+        assert_eq!(z1.get_pixel(3, 3), &Rgba([0, 0, 0, 0]));
+
+        let z0 = pmtiles.get_raster_tile(0, 0, 0).unwrap().unwrap();
+        let z0 = RgbaImage::from_raw(z0.width, z0.height, z0.pixels).unwrap();
+        assert_eq!(z0.dimensions(), (4, 4));
+        assert_eq!(z0.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(z0.get_pixel(3, 0), &Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn build_overviews_from_single_run() {
+        let (input, output) = (TempPath::new("overview_run_in"), TempPath::new("overview_run_out"));
+        let header = Header { min_zoom: 3, max_zoom: 3, ..test_header(TileType::PNG, Compression::None) };
+        // z3の全タイルが同じ内容の1エントリ
+        let ocean = encode_tile(&RasterTile { width: 4, height: 4, pixels: [0, 0, 255, 255].repeat(16) }, TileType::PNG).unwrap();
+        let tiles = (0..64).map(|i| (TileId::new(TileId::encode(3, 0, 0).value() + i), &ocean));
+        std::fs::write(&input, archive_bytes(header, "{}", tiles)).unwrap();
+
+        let header = build_overviews(input.as_str(), output.as_str(), 1).unwrap();
+        let pmtiles = PMTiles::open(output.as_str()).unwrap();
+
+        assert_eq!(header.num_addressed_tiles, 4 + 16 + 64);
+        assert_eq!(pmtiles.get_tile(0, 0, 0).unwrap(), None);
+        let z1 = pmtiles.get_raster_tile(1, 0, 1).unwrap().unwrap();
+        assert!(z1.pixels.chunks(4).all(|pixel| pixel == [0, 0, 255, 255]));
+    }
+}
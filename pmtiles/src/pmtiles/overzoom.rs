@@ -0,0 +1,65 @@
+use std::borrow::Cow;
+use std::io;
+
+use super::PMTiles;
+use super::compression::{compress, decompress};
+use super::types::TileType;
+use crate::mvt::Tile;
+use crate::mvt::clip::overzoom;
+use crate::tileid::MAX_ZOOM;
+
+impl PMTiles {
+    /// Like `get_tile`, but for MVT archives a tile above `header.max_zoom` is synthesized
+    /// from its ancestor at `max_zoom` by clipping and rescaling the geometry.
+    /// The result is compressed with `header.tile_compression`, like stored tiles.
+    pub fn get_tile_or_overzoom(&self, z: u8, x: u32, y: u32) -> io::Result<Option<Cow<'_, [u8]>>> {
+        let max_zoom = self.header.max_zoom;
+        if z <= max_zoom || z > MAX_ZOOM || self.header.tile_type != TileType::MVT {
+            return Ok(self.get_tile(z, x, y)?.map(Cow::Borrowed));
+        }
+        if let Some(data) = self.get_tile(z, x, y)? {
+            return Ok(Some(Cow::Borrowed(data)));
+        }
+
+        let dz = z - max_zoom;
+        let Some(parent) = self.get_tile(max_zoom, x >> dz, y >> dz)? else {
+            return Ok(None);
+        };
+        let parent = Tile::decode(&decompress(parent, self.header.tile_compression)?)?;
+        // 親タイル内での子タイルの位置
+        let mask = (1u32 << dz) - 1;
+        let child = overzoom(&parent, dz, x & mask, y & mask);
+        log::debug!("Overzoomed {}/{}/{} from zoom {}", z, x, y, max_zoom);
+        Ok(Some(Cow::Owned(compress(&child.encode(), self.header.tile_compression)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mvt::{Feature, GeomType, Layer};
+    use crate::pmtiles::testing::{open_archive, test_header};
+    use crate::pmtiles::types::Compression;
+    use crate::tileid::TileId;
+
+    #[test]
+    fn overzoom_beyond_max_zoom() {
+        let mut layer = Layer::new("poi");
+        layer.features.push(Feature { geom_type: GeomType::Point, geometry: vec![vec![(1000, 3000)]], ..Default::default() });
+        let tile = Tile { layers: vec![layer] };
+        let data = compress(&tile.encode(), Compression::Gzip).unwrap();
+        let pmtiles = open_archive(test_header(TileType::MVT, Compression::Gzip), "{}", [(TileId::encode(1, 1, 0), data)]);
+
+        assert!(matches!(pmtiles.get_tile_or_overzoom(1, 1, 0).unwrap(), Some(Cow::Borrowed(_))));
+        assert_eq!(pmtiles.get_tile_or_overzoom(1, 0, 0).unwrap(), None);
+
+        // z3の(4,2)はz1の(1,0)の中で左から0番目、上から2番目
+        let data = pmtiles.get_tile_or_overzoom(3, 4, 2).unwrap().unwrap();
+        let child = Tile::decode(&decompress(&data, Compression::Gzip).unwrap()).unwrap();
+        assert_eq!(child.layers[0].features[0].geometry, vec![vec![(4000, 12000 - 2 * 4096)]]);
+
+        let data = pmtiles.get_tile_or_overzoom(3, 5, 1).unwrap().unwrap();
+        assert!(Tile::decode(&decompress(&data, Compression::Gzip).unwrap()).unwrap().layers.is_empty());
+        assert_eq!(pmtiles.get_tile_or_overzoom(3, 0, 0).unwrap(), None);
+    }
+}
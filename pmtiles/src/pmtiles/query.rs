@@ -0,0 +1,164 @@
+use std::io;
+
+use super::PMTiles;
+use super::compression::decompress;
+use super::types::TileType;
+use crate::mvt::{Feature, GeomType, Point, Tile};
+use crate::tileid::{MAX_ZOOM, lon_lat_to_tile_position};
+
+/// Pixels per tile side used to convert the query tolerance into tile units.
+pub const TILE_SIZE_PX: f64 = 256.0;
+
+/// A feature found by `query_point`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryHit {
+    pub layer: String,
+    /// Distance from the query point in pixels; 0 when the point is inside a polygon.
+    pub distance: f64,
+    pub feature: Feature,
+}
+
+type Position = (f64, f64);
+
+fn to_position(&(x, y): &Point) -> Position {
+    (x as f64, y as f64)
+}
+
+fn segment_distance(p: Position, a: Position, b: Position) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared == 0.0 { 0.0 } else { (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_squared).clamp(0.0, 1.0) };
+    ((p.0 - a.0 - t * dx).powi(2) + (p.1 - a.1 - t * dy).powi(2)).sqrt()
+}
+
+/// Even-odd rule over every ring, so holes are excluded.
+fn polygon_contains(rings: &[Vec<Point>], p: Position) -> bool {
+    let mut inside = false;
+    for ring in rings {
+        for (i, a) in ring.iter().enumerate() {
+            let (a, b) = (to_position(a), to_position(&ring[(i + 1) % ring.len()]));
+            if (a.1 > p.1) != (b.1 > p.1) && p.0 < a.0 + (p.1 - a.1) / (b.1 - a.1) * (b.0 - a.0) {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// Distance in tile units from `p` to the feature's geometry.
+fn feature_distance(feature: &Feature, p: Position) -> Option<f64> {
+    let closed = feature.geom_type == GeomType::Polygon;
+    if closed && polygon_contains(&feature.geometry, p) {
+        return Some(0.0);
+    }
+    let mut distance = None;
+    let mut update = |d: f64| distance = Some(distance.map_or(d, |min: f64| min.min(d)));
+    for part in &feature.geometry {
+        match feature.geom_type {
+            GeomType::Point => part.iter().for_each(|point| update(segment_distance(p, to_position(point), to_position(point)))),
+            GeomType::LineString | GeomType::Polygon => {
+                let segments = if closed { part.len() } else { part.len().saturating_sub(1) };
+                for i in 0..segments {
+                    update(segment_distance(p, to_position(&part[i]), to_position(&part[(i + 1) % part.len()])));
+                }
+            },
+            GeomType::Unknown => {},
+        }
+    }
+    distance
+}
+
+impl PMTiles {
+    /// Finds the features of the MVT tile at `zoom` covering (`lon`, `lat`) that contain the
+    /// point or lie within `tolerance` pixels of it, nearest first. Zooms above
+    /// `header.max_zoom` are answered by overzooming. Features of neighbouring tiles are
+    /// not considered.
+    pub fn query_point(&self, lon: f64, lat: f64, zoom: u8, tolerance: f64) -> io::Result<Vec<QueryHit>> {
+        if self.header.tile_type != TileType::MVT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Point queries need an MVT archive, not {}", self.header.tile_type)
+            ));
+        }
+        if zoom > MAX_ZOOM {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Zoom {} is beyond {}", zoom, MAX_ZOOM)));
+        }
+        let (tile_x, tile_y) = lon_lat_to_tile_position(zoom, lon, lat);
+        let max = ((1u64 << zoom) - 1) as f64;
+        let (x, y) = (tile_x.floor().clamp(0.0, max), tile_y.floor().clamp(0.0, max));
+
+        let Some(data) = self.get_tile_or_overzoom(zoom, x as u32, y as u32)? else {
+            return Ok(Vec::new());
+        };
+        let tile = Tile::decode(&decompress(&data, self.header.tile_compression)?)?;
+
+        let mut hits = Vec::new();
+        for layer in tile.layers {
+            let extent = layer.extent as f64;
+            // タイル内の座標に変換
+            let p = ((tile_x - x) * extent, (tile_y - y) * extent);
+            let pixels_per_unit = TILE_SIZE_PX / extent;
+            for feature in layer.features {
+                if let Some(distance) = feature_distance(&feature, p).map(|d| d * pixels_per_unit)
+                    && distance <= tolerance {
+                    hits.push(QueryHit { layer: layer.name.clone(), distance, feature });
+                }
+            }
+        }
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mvt::{Layer, Value};
+    use crate::pmtiles::compression::compress;
+    use crate::pmtiles::testing::{open_archive, test_header};
+    use crate::pmtiles::types::Compression;
+    use crate::tileid::TileId;
+
+    fn feature(name: &str, geom_type: GeomType, geometry: Vec<Vec<Point>>) -> Feature {
+        Feature { properties: vec![("name".to_string(), Value::String(name.to_string()))], geom_type, geometry, ..Default::default() }
+    }
+
+    #[test]
+    fn query_features_at_point() {
+        // z1の(1,0)タイル: 経度0..180、緯度0..85
+        let mut layer = Layer::new("test");
+        layer.features.push(feature("park", GeomType::Polygon, vec![
+            vec![(0, 0), (2048, 0), (2048, 2048), (0, 2048)],
+            vec![(100, 100), (100, 300), (300, 300), (300, 100)],
+        ]));
+        layer.features.push(feature("river", GeomType::LineString, vec![vec![(0, 1120), (4096, 1120)]]));
+        layer.features.push(feature("station", GeomType::Point, vec![vec![(1024, 1024)]]));
+        let tile = Tile { layers: vec![layer] };
+
+        let data = compress(&tile.encode(), Compression::Gzip).unwrap();
+        let pmtiles = open_archive(test_header(TileType::MVT, Compression::Gzip), "{}", [(TileId::encode(1, 1, 0), data)]);
+
+        let names = |hits: Vec<QueryHit>| -> Vec<String> {
+            hits.iter().map(|hit| match hit.feature.property("name") {
+                Some(Value::String(name)) => name.clone(),
+                _ => panic!("feature without name"),
+            }).collect()
+        };
+
+        // タイル中心(1024,1024)付近 = 経度45度
+        let (lon, lat) = (45.0, 79.171_334_64);
+        let hits = pmtiles.query_point(lon, lat, 1, 5.0).unwrap();
+        assert_eq!(names(hits.clone()), ["park", "station"]);
+        assert_eq!(hits[0].distance, 0.0);
+        assert!(hits[1].distance < 1.0);
+        // 許容距離を広げると川(y=1120、6px南)も入る
+        assert_eq!(names(pmtiles.query_point(lon, lat, 1, 7.0).unwrap()), ["park", "station", "river"]);
+        // 穴の中
+        let hole = pmtiles.query_point(180.0 * 200.0 / 4096.0, 84.231_947, 1, 0.0).unwrap();
+        assert!(hole.is_empty());
+        // 何もないタイル
+        assert!(pmtiles.query_point(-45.0, 45.0, 1, 100.0).unwrap().is_empty());
+        // max_zoomより深いズームは親タイルから切り出す
+        assert_eq!(names(pmtiles.query_point(lon, lat, 3, 2.0).unwrap()), ["park", "station"]);
+    }
+}
@@ -0,0 +1,205 @@
+use std::io;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageFormat, RgbaImage, imageops};
+
+use super::{MAX_DIRECTORY_DEPTH, PMTiles};
+use super::compression::decompress;
+use super::types::TileType;
+use crate::tileid::lon_lat_to_xy;
+
+/// Mosaics larger than this (in pixels per side) are rejected.
+const MAX_MOSAIC_SIZE: u32 = 16_384;
+const JPEG_QUALITY: u8 = 85;
+
+/// A decoded raster tile as RGBA8 pixels, row by row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RasterTile {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+fn image_format(tile_type: TileType) -> io::Result<ImageFormat> {
+    match tile_type {
+        TileType::PNG => Ok(ImageFormat::Png),
+        TileType::JPEG => Ok(ImageFormat::Jpeg),
+        TileType::WebP => Ok(ImageFormat::WebP),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Unsupported raster tile type: {}", tile_type)
+        )),
+    }
+}
+
+pub(crate) fn to_io_error(e: image::ImageError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Decodes an uncompressed PNG/JPEG/WebP tile.
+pub fn decode_tile(data: &[u8], tile_type: TileType) -> io::Result<RasterTile> {
+    let image = image::load_from_memory_with_format(data, image_format(tile_type)?)
+        .map_err(to_io_error)?
+        .to_rgba8();
+    Ok(RasterTile { width: image.width(), height: image.height(), pixels: image.into_raw() })
+}
+
+/// Encodes RGBA8 pixels as a PNG/JPEG/WebP tile. JPEG drops the alpha channel
+/// and WebP is written lossless.
+pub fn encode_tile(tile: &RasterTile, tile_type: TileType) -> io::Result<Vec<u8>> {
+    let image = RgbaImage::from_raw(tile.width, tile.height, tile.pixels.clone())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Pixel buffer does not match the tile size"))?;
+    let mut data = Vec::new();
+    match image_format(tile_type)? {
+        ImageFormat::Jpeg => DynamicImage::ImageRgba8(image).to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY)),
+        ImageFormat::WebP => image.write_with_encoder(WebPEncoder::new_lossless(&mut data)),
+        format => image.write_to(&mut io::Cursor::new(&mut data), format),
+    }.map_err(to_io_error)?;
+    Ok(data)
+}
+
+impl PMTiles {
+    /// Looks up and decodes a raster tile, undoing `header.tile_compression` first.
+    pub fn get_raster_tile(&self, z: u8, x: u32, y: u32) -> io::Result<Option<RasterTile>> {
+        match self.get_tile(z, x, y)? {
+            Some(data) => {
+                let data = decompress(data, self.header.tile_compression)?;
+                decode_tile(&data, self.header.tile_type).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Stitches every tile at zoom `z` covering the bounds into a single PNG.
+    /// Missing tiles are left transparent. Returns the size of the written image.
+    pub fn mosaic(&self, z: u8, min_position: (f64, f64), max_position: (f64, f64), output_path: &str) -> io::Result<(u32, u32)> {
+        // 北西の角が左上のタイルになる
+        let (min_x, min_y) = lon_lat_to_xy(z, min_position.0, max_position.1);
+        let (max_x, max_y) = lon_lat_to_xy(z, max_position.0, min_position.1);
+
+        let (columns, rows) = ((max_x as u64 + 1).saturating_sub(min_x as u64), (max_y as u64 + 1).saturating_sub(min_y as u64));
+        let check_size = |tile_size: u32| {
+            let (width, height) = (columns * tile_size as u64, rows * tile_size as u64);
+            if width > MAX_MOSAIC_SIZE as u64 || height > MAX_MOSAIC_SIZE as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Mosaic of {}x{} pixels is too large, try a lower zoom", width, height)
+                ));
+            }
+            Ok((width as u32, height as u32))
+        };
+        // タイルを探してデコードする前に、範囲とアーカイブ先頭のタイルの大きさで判定する
+        check_size(1)?;
+        let mut tile_size = self.first_tile_size()?.unwrap_or(1);
+        check_size(tile_size)?;
+
+        let mut tiles = Vec::new();
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                if let Some(tile) = self.get_raster_tile(z, x, y)? {
+                    if tile.width.max(tile.height) > tile_size {
+                        tile_size = tile.width.max(tile.height);
+                        check_size(tile_size)?;
+                    }
+                    tiles.push((x - min_x, y - min_y, tile));
+                }
+            }
+        }
+        if tiles.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("No tiles at zoom {} within the bounds", z)));
+        }
+        let (width, height) = check_size(tile_size)?;
+
+        let mut mosaic = RgbaImage::new(width, height);
+        for (column, row, tile) in tiles {
+            let mut image = RgbaImage::from_raw(tile.width, tile.height, tile.pixels)
+                .expect("pixel buffer matches the tile size");
+            if tile.width != tile_size || tile.height != tile_size {
+                image = imageops::resize(&image, tile_size, tile_size, imageops::FilterType::Triangle);
+            }
+            imageops::replace(&mut mosaic, &image, (column * tile_size) as i64, (row * tile_size) as i64);
+        }
+        mosaic.save_with_format(output_path, ImageFormat::Png).map_err(to_io_error)?;
+
+        Ok((mosaic.width(), mosaic.height()))
+    }
+
+    /// Side of the first tile in the archive, following leaves; raster archives normally
+    /// use one tile size throughout.
+    fn first_tile_size(&self) -> io::Result<Option<u32>> {
+        let mut entry = self.root_directory.entries.first().cloned();
+        for _ in 0..MAX_DIRECTORY_DEPTH {
+            match entry {
+                None => return Ok(None),
+                Some(entry) if entry.run_length > 0 => {
+                    let data = decompress(self.tile_data(&entry)?, self.header.tile_compression)?;
+                    let tile = decode_tile(&data, self.header.tile_type)?;
+                    return Ok(Some(tile.width.max(tile.height)));
+                },
+                Some(leaf_entry) => entry = self.read_leaf(&leaf_entry)?.entries.into_iter().next(),
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::InvalidData, "Leaf directories are nested too deeply"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use image::Rgba;
+    use crate::pmtiles::testing::{TempPath, test_header, write_archive};
+    use crate::pmtiles::types::Compression;
+    use crate::tileid::TileId;
+
+    fn png_tile(color: [u8; 4]) -> Vec<u8> {
+        let image = RgbaImage::from_pixel(2, 2, Rgba(color));
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, ImageFormat::Png).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let tile = RasterTile { width: 2, height: 1, pixels: vec![255, 0, 0, 255, 0, 0, 255, 128] };
+        for tile_type in [TileType::PNG, TileType::WebP] {
+            assert_eq!(decode_tile(&encode_tile(&tile, tile_type).unwrap(), tile_type).unwrap(), tile);
+        }
+        let jpeg = decode_tile(&encode_tile(&tile, TileType::JPEG).unwrap(), TileType::JPEG).unwrap();
+        assert_eq!((jpeg.width, jpeg.height, jpeg.pixels[3]), (2, 1, 255));
+        assert!(encode_tile(&tile, TileType::MVT).is_err());
+    }
+
+    #[test]
+    fn decode_and_mosaic() {
+        let (archive, mosaic) = (TempPath::new("raster"), TempPath::with_extension("mosaic", "png"));
+        let mosaic = mosaic.as_str();
+
+        // z1の(1,1)は欠けたままにする
+        let tiles = [((0, 0), [255, 0, 0, 255]), ((0, 1), [0, 255, 0, 255]), ((1, 0), [0, 0, 255, 255])];
+        let mut tiles: Vec<_> = tiles.iter().map(|&((x, y), color)| (TileId::encode(1, x, y), png_tile(color))).collect();
+        tiles.sort_by_key(|(tile_id, _)| tile_id.value());
+        write_archive(&archive, test_header(TileType::PNG, Compression::None), "{}", tiles);
+
+        let pmtiles = PMTiles::open(archive.as_str()).unwrap();
+        let tile = pmtiles.get_raster_tile(1, 0, 1).unwrap().unwrap();
+        assert_eq!((tile.width, tile.height), (2, 2));
+        assert_eq!(&tile.pixels[0..4], &[0, 255, 0, 255]);
+
+        let size = pmtiles.mosaic(1, (-180.0, -85.0), (180.0, 85.0), mosaic).unwrap();
+        let image = image::open(mosaic).unwrap().to_rgba8();
+
+        assert_eq!(size, (4, 4));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(3, 0), &Rgba([0, 0, 255, 255]));
+        assert_eq!(image.get_pixel(0, 3), &Rgba([0, 255, 0, 255]));
+        assert_eq!(image.get_pixel(3, 3), &Rgba([0, 0, 0, 0]));
+
+        // 範囲だけで、あるいは先頭のタイルの大きさで、タイルを引く前に断る
+        let world = ((-180.0, -85.0), (180.0, 85.0));
+        assert_eq!(pmtiles.mosaic(18, world.0, world.1, mosaic).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(pmtiles.mosaic(14, world.0, world.1, mosaic).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,263 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+use super::PMTiles;
+use super::directory::DirectoryEntry;
+use crate::tileid::{MAX_TILE_ID_END, MAX_ZOOM, TileId};
+
+/// Counts gathered by walking every directory of an archive.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TileStats {
+    pub num_addressed_tiles: u64,
+    pub num_tile_entries: u64,
+    /// Distinct (offset, length) pairs.
+    pub num_tile_contents: u64,
+    /// Total length of the distinct contents.
+    pub tile_data_length: u64,
+    pub tiles_per_zoom: BTreeMap<u8, u64>,
+}
+
+impl TileStats {
+    pub fn print_info(&self) {
+        println!("Tile Stats:");
+        println!("  Tiles: addressed={}, entries={}, contents={}",
+            self.num_addressed_tiles, self.num_tile_entries, self.num_tile_contents);
+        println!("  Tile Data: {} bytes", self.tile_data_length);
+        for (z, count) in &self.tiles_per_zoom {
+            println!("  Zoom {}: {} tiles", z, count);
+        }
+    }
+}
+
+/// Stats of one chunk of entries, still holding the contents set so chunks can be merged.
+#[derive(Debug, Default)]
+struct PartialStats {
+    stats: TileStats,
+    contents: HashSet<(usize, usize)>,
+}
+
+impl PartialStats {
+    fn from_entries(entries: &[DirectoryEntry]) -> Self {
+        let mut partial = PartialStats::default();
+        for entry in entries {
+            partial.stats.num_tile_entries += 1;
+            partial.stats.num_addressed_tiles += entry.run_length as u64;
+            add_run_per_zoom(&mut partial.stats.tiles_per_zoom, entry.tileid.value(), entry.run_length as u64);
+            partial.contents.insert((entry.offset, entry.length));
+        }
+        partial
+    }
+
+    fn merge(mut self, other: PartialStats) -> Self {
+        self.stats.num_tile_entries += other.stats.num_tile_entries;
+        self.stats.num_addressed_tiles += other.stats.num_addressed_tiles;
+        for (z, count) in other.stats.tiles_per_zoom {
+            *self.stats.tiles_per_zoom.entry(z).or_default() += count;
+        }
+        self.contents.extend(other.contents);
+        self
+    }
+
+    fn finish(mut self) -> TileStats {
+        self.stats.num_tile_contents = self.contents.len() as u64;
+        self.stats.tile_data_length = self.contents.iter().map(|&(_, length)| length as u64).sum();
+        self.stats
+    }
+}
+
+/// Adds a run of tiles to the per-zoom counts; a run may cross into the next zoom level.
+fn add_run_per_zoom(tiles_per_zoom: &mut BTreeMap<u8, u64>, start: u64, run_length: u64) {
+    let end = start + run_length;
+    let mut tile_id = start;
+    while tile_id < end {
+        let (z, _, _) = TileId::new(tile_id).decode();
+        // z=31の次のズームは64bitに収まらない
+        let next_zoom_start = if z < MAX_ZOOM { TileId::encode(z + 1, 0, 0).value() } else { MAX_TILE_ID_END };
+        let count = end.min(next_zoom_start) - tile_id;
+        *tiles_per_zoom.entry(z).or_default() += count;
+        tile_id += count;
+    }
+}
+
+/// Problems found in one chunk, plus the TileID range it covers for cross-chunk checks.
+#[derive(Debug, Default)]
+struct ChunkCheck {
+    problems: Vec<String>,
+    range: Option<(u64, u64)>,
+}
+
+impl PMTiles {
+    /// Tile entries under one root entry: the entry itself, or everything in its leaf.
+    fn chunk_entries(&self, root_entry: &DirectoryEntry) -> io::Result<Vec<DirectoryEntry>> {
+        if root_entry.run_length > 0 {
+            return Ok(vec![root_entry.clone()]);
+        }
+        let mut entries = Vec::new();
+        self.collect_tile_entries(&self.read_leaf(root_entry)?, &(0..u64::MAX), 2, &mut entries)?;
+        Ok(entries)
+    }
+
+    fn check_chunk(&self, entries: &[DirectoryEntry]) -> ChunkCheck {
+        let mut check = ChunkCheck::default();
+        for entry in entries {
+            let start = entry.tileid.value();
+            let end = start + entry.run_length as u64;
+            if let Some((_, last_end)) = check.range && start < last_end {
+                check.problems.push(format!("Entry for TileID {} overlaps or precedes the previous entry", start));
+            }
+            if entry.offset + entry.length > self.header.tile_data_length {
+                check.problems.push(format!(
+                    "Entry for TileID {} points outside of tile data: offset={}, length={}", start, entry.offset, entry.length
+                ));
+            }
+            let (z, _, _) = entry.tileid.decode();
+            if z < self.header.min_zoom || z > self.header.max_zoom {
+                check.problems.push(format!("Entry for TileID {} has zoom {} outside of the header's zoom range", start, z));
+            }
+            check.range = Some((check.range.map_or(start, |(first, _)| first), end));
+        }
+        check
+    }
+
+    fn merge_checks(&self, checks: Vec<ChunkCheck>, stats: &TileStats) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut last_end = None;
+        for check in checks {
+            if let (Some(last_end), Some((first, _))) = (last_end, check.range) && first < last_end {
+                problems.push(format!("Directory starting at TileID {} overlaps the previous directory", first));
+            }
+            last_end = check.range.map(|(_, end)| end).or(last_end);
+            problems.extend(check.problems);
+        }
+
+        let counts = [
+            ("num_addressed_tiles", self.header.num_addressed_tiles, stats.num_addressed_tiles),
+            ("num_tile_entries", self.header.num_tile_entries, stats.num_tile_entries),
+            ("num_tile_contents", self.header.num_tile_contents, stats.num_tile_contents),
+        ];
+        for (name, expected, actual) in counts {
+            if expected != actual {
+                problems.push(format!("Header {} is {} but the directories contain {}", name, expected, actual));
+            }
+        }
+        problems
+    }
+
+    pub fn stats(&self) -> io::Result<TileStats> {
+        let mut partial = PartialStats::default();
+        for root_entry in &self.root_directory.entries {
+            partial = partial.merge(PartialStats::from_entries(&self.chunk_entries(root_entry)?));
+        }
+        Ok(partial.finish())
+    }
+
+    /// Checks directory ordering, tile data bounds, zoom range and the header counts.
+    /// Returns a description of each problem found; an empty list means the archive is consistent.
+    pub fn verify(&self) -> io::Result<Vec<String>> {
+        let mut partial = PartialStats::default();
+        let mut checks = Vec::new();
+        for root_entry in &self.root_directory.entries {
+            let entries = self.chunk_entries(root_entry)?;
+            checks.push(self.check_chunk(&entries));
+            partial = partial.merge(PartialStats::from_entries(&entries));
+        }
+        Ok(self.merge_checks(checks, &partial.finish()))
+    }
+}
+
+/// Parallel variants that decode each leaf directory on the rayon thread pool.
+/// Results are combined in directory order, so they match the serial versions exactly.
+#[cfg(feature = "parallel")]
+impl PMTiles {
+    pub fn par_tile_entries(&self) -> io::Result<Vec<DirectoryEntry>> {
+        let chunks = self.root_directory.entries.par_iter()
+            .map(|root_entry| self.chunk_entries(root_entry))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(chunks.into_iter().flatten().collect())
+    }
+
+    pub fn par_stats(&self) -> io::Result<TileStats> {
+        let partial = self.root_directory.entries.par_iter()
+            .map(|root_entry| self.chunk_entries(root_entry).map(|entries| PartialStats::from_entries(&entries)))
+            .try_reduce(PartialStats::default, |a, b| Ok(a.merge(b)))?;
+        Ok(partial.finish())
+    }
+
+    pub fn par_verify(&self) -> io::Result<Vec<String>> {
+        let chunks = self.root_directory.entries.par_iter()
+            .map(|root_entry| {
+                let entries = self.chunk_entries(root_entry)?;
+                Ok((self.check_chunk(&entries), PartialStats::from_entries(&entries)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let (checks, partials): (Vec<_>, Vec<_>) = chunks.into_iter().unzip();
+        let stats = partials.into_iter().fold(PartialStats::default(), PartialStats::merge).finish();
+        Ok(self.merge_checks(checks, &stats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::edit::{HeaderEdit, edit_in_place};
+    use crate::pmtiles::header::Header;
+    use crate::pmtiles::testing::{TempPath, test_header, write_archive};
+    use crate::pmtiles::types::{Compression, TileType};
+
+    const NUM_TILES: u64 = 20_000;
+
+    /// Enough distinct tiles to need leaf directories; every fourth tile shares one content.
+    fn tiles() -> impl Iterator<Item = (TileId, [u8; 8])> {
+        (0..NUM_TILES).map(|i| (TileId::new(i), if i % 4 == 0 { 0u64 } else { i }.to_le_bytes()))
+    }
+
+    #[test]
+    fn stats_and_verify() {
+        let temp_path = TempPath::new("scan");
+        let file_path = temp_path.as_str();
+        write_archive(&temp_path, Header { max_zoom: 8, ..test_header(TileType::MVT, Compression::None) }, "{}", tiles());
+
+        let pmtiles = PMTiles::open(file_path).unwrap();
+        let header = &pmtiles.header;
+        assert!(pmtiles.root_directory.entries.iter().all(|entry| entry.run_length == 0));
+
+        let stats = pmtiles.stats().unwrap();
+        assert_eq!(stats.num_addressed_tiles, NUM_TILES);
+        assert_eq!(stats.num_tile_entries, header.num_tile_entries);
+        assert_eq!(stats.num_tile_contents, header.num_tile_contents);
+        assert_eq!(stats.tile_data_length, header.tile_data_length as u64);
+        assert_eq!(stats.tiles_per_zoom[&0], 1);
+        assert_eq!(stats.tiles_per_zoom[&6], 4u64.pow(6));
+        assert_eq!(stats.tiles_per_zoom.values().sum::<u64>(), NUM_TILES);
+        assert_eq!(pmtiles.verify().unwrap(), Vec::<String>::new());
+
+        let edit = HeaderEdit { max_zoom: Some(6), ..Default::default() };
+        edit_in_place(file_path, &edit, None).unwrap();
+        let problems = PMTiles::open(file_path).unwrap().verify().unwrap();
+        assert!(problems.iter().all(|problem| problem.contains("outside of the header's zoom range")));
+        assert!(!problems.is_empty());
+    }
+
+    #[test]
+    fn run_at_max_zoom() {
+        let mut tiles_per_zoom = BTreeMap::new();
+        add_run_per_zoom(&mut tiles_per_zoom, MAX_TILE_ID_END - 10, 10);
+        add_run_per_zoom(&mut tiles_per_zoom, TileId::encode(MAX_ZOOM, 0, 0).value() - 2, 5);
+        assert_eq!(tiles_per_zoom, BTreeMap::from([(30, 2), (31, 13)]));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_matches_serial() {
+        let header = Header { max_zoom: 8, ..test_header(TileType::MVT, Compression::None) };
+        let pmtiles = crate::pmtiles::testing::open_archive(header, "{}", tiles());
+
+        assert_eq!(pmtiles.par_tile_entries().unwrap(), pmtiles.tile_entries().unwrap());
+        assert_eq!(pmtiles.par_stats().unwrap(), pmtiles.stats().unwrap());
+        assert_eq!(pmtiles.par_verify().unwrap(), pmtiles.verify().unwrap());
+    }
+}
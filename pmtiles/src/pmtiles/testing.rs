@@ -0,0 +1,67 @@
+//! Archive fixtures shared by the unit tests.
+
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use super::PMTiles;
+use super::header::Header;
+use super::metadata::Metadata;
+use super::types::{Compression, TileType};
+use super::writer::Writer;
+use crate::tileid::TileId;
+
+/// A file in the temp directory that is removed on drop, so failing tests do not leak it.
+pub(crate) struct TempPath(String);
+
+impl TempPath {
+    pub(crate) fn new(name: &str) -> Self {
+        TempPath::with_extension(name, "pmtiles")
+    }
+
+    pub(crate) fn with_extension(name: &str, extension: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("pmtiles_{}_{}.{}", name, std::process::id(), extension));
+        TempPath(path.to_str().unwrap().to_string())
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Gzip directories and zooms 0..=1; override the rest with struct update syntax.
+pub(crate) fn test_header(tile_type: TileType, tile_compression: Compression) -> Header {
+    Header { internal_compression: Compression::Gzip, tile_compression, tile_type, max_zoom: 1, ..Default::default() }
+}
+
+/// Writes `tiles`, in ascending TileID order, into an archive held in memory.
+pub(crate) fn archive_bytes<T: AsRef<[u8]>>(header: Header, metadata: &str, tiles: impl IntoIterator<Item = (TileId, T)>) -> Vec<u8> {
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    for (tile_id, data) in tiles {
+        writer.add_tile(tile_id, data.as_ref()).unwrap();
+    }
+    let mut data = Vec::new();
+    writer.finish(&mut data, header, &Metadata::from_json(metadata).unwrap()).unwrap();
+    data
+}
+
+/// Like `archive_bytes`, but writes the archive to `path`.
+pub(crate) fn write_archive<T: AsRef<[u8]>>(path: &TempPath, header: Header, metadata: &str, tiles: impl IntoIterator<Item = (TileId, T)>) {
+    fs::write(path, archive_bytes(header, metadata, tiles)).unwrap();
+}
+
+pub(crate) fn open_archive<T: AsRef<[u8]>>(header: Header, metadata: &str, tiles: impl IntoIterator<Item = (TileId, T)>) -> PMTiles {
+    PMTiles::from_bytes(archive_bytes(header, metadata, tiles)).unwrap()
+}
@@ -0,0 +1,93 @@
+use std::io;
+
+use serde_json::{Map, Value, json};
+
+use super::PMTiles;
+use super::header::Header;
+use super::metadata::Metadata;
+use super::types::TileType;
+
+pub const TILEJSON_VERSION: &str = "3.0.0";
+
+/// Metadata keys copied into the TileJSON document as they are.
+const METADATA_KEYS: [&str; 6] = ["name", "description", "attribution", "version", "legend", "fillzoom"];
+
+/// Builds a TileJSON 3.0 document. `url_template` is the tile URL with `{z}`, `{x}` and `{y}`
+/// placeholders, e.g. `https://example.com/tiles/{z}/{x}/{y}.mvt`.
+pub fn tilejson(header: &Header, metadata: &Metadata, url_template: &str) -> io::Result<Value> {
+    let metadata: Value = serde_json::from_str(metadata.json())?;
+
+    let mut document = Map::new();
+    document.insert("tilejson".to_string(), json!(TILEJSON_VERSION));
+    document.insert("tiles".to_string(), json!([url_template]));
+    document.insert("scheme".to_string(), json!("xyz"));
+    document.insert("minzoom".to_string(), json!(header.min_zoom));
+    document.insert("maxzoom".to_string(), json!(header.max_zoom));
+    document.insert("bounds".to_string(), json!([
+        header.min_position.0, header.min_position.1, header.max_position.0, header.max_position.1,
+    ]));
+    document.insert("center".to_string(), json!([header.center_position.0, header.center_position.1, header.center_zoom]));
+
+    for key in METADATA_KEYS {
+        if let Some(value) = metadata.get(key) {
+            document.insert(key.to_string(), value.clone());
+        }
+    }
+    // ベクタータイルではvector_layersが必須
+    match metadata.get("vector_layers") {
+        Some(vector_layers) => { document.insert("vector_layers".to_string(), vector_layers.clone()); },
+        None if header.tile_type == TileType::MVT => { document.insert("vector_layers".to_string(), json!([])); },
+        None => {},
+    }
+
+    Ok(Value::Object(document))
+}
+
+impl PMTiles {
+    pub fn tilejson(&self, url_template: &str) -> io::Result<Value> {
+        tilejson(&self.header, &self.metadata, url_template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tilejson_from_header_and_metadata() {
+        let header = Header {
+            tile_type: TileType::MVT,
+            min_zoom: 4,
+            max_zoom: 16,
+            min_position: (122.0, 17.03498),
+            max_position: (154.766667, 46.0),
+            center_zoom: 16,
+            center_position: (135.601501, 34.8295869),
+            ..Default::default()
+        };
+        let metadata = Metadata::from_json(r#"{
+            "name": "bvmap",
+            "attribution": "GSI",
+            "generator": "tippecanoe",
+            "vector_layers": [{"id": "road", "fields": {"name": "String"}}]
+        }"#).unwrap();
+
+        let document = tilejson(&header, &metadata, "https://example.com/{z}/{x}/{y}.pbf").unwrap();
+        assert_eq!(document, json!({
+            "tilejson": "3.0.0",
+            "tiles": ["https://example.com/{z}/{x}/{y}.pbf"],
+            "scheme": "xyz",
+            "minzoom": 4,
+            "maxzoom": 16,
+            "bounds": [122.0, 17.03498, 154.766667, 46.0],
+            "center": [135.601501, 34.8295869, 16],
+            "name": "bvmap",
+            "attribution": "GSI",
+            "vector_layers": [{"id": "road", "fields": {"name": "String"}}],
+        }));
+
+        let raster = Header { tile_type: TileType::PNG, ..header };
+        let document = tilejson(&raster, &Metadata::from_json("{}").unwrap(), "/{z}/{x}/{y}.png").unwrap();
+        assert!(document.get("vector_layers").is_none());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+
+use serde_json::{Value as JsonValue, json};
+
+use super::PMTiles;
+use super::compression::decompress;
+use super::metadata::Metadata;
+use super::types::TileType;
+use crate::mvt::{GeomType, Tile, Value};
+
+/// Default number of distinct sample values kept per attribute, as in mapbox-geostats.
+pub const DEFAULT_MAX_VALUES: usize = 100;
+
+#[derive(Debug, Default)]
+struct AttributeStats {
+    types: BTreeMap<&'static str, u64>,
+    /// Distinct values in the order first seen, capped at `max_values`.
+    values: Vec<JsonValue>,
+    seen: HashSet<String>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl AttributeStats {
+    fn add(&mut self, value: &Value, max_values: usize) {
+        let (value_type, json) = match (value, value.as_f64()) {
+            (Value::String(string), _) => ("string", json!(string)),
+            (Value::Bool(boolean), _) => ("boolean", json!(boolean)),
+            (_, None) => return,
+            (_, Some(number)) => {
+                self.min = Some(self.min.map_or(number, |min| min.min(number)));
+                self.max = Some(self.max.map_or(number, |max| max.max(number)));
+                ("number", json!(number))
+            },
+        };
+        *self.types.entry(value_type).or_default() += 1;
+        if self.values.len() < max_values && self.seen.insert(json.to_string()) {
+            self.values.push(json);
+        }
+    }
+
+    fn to_json(&self, name: &str) -> JsonValue {
+        let mut types = self.types.keys();
+        let value_type = match (types.next(), types.next()) {
+            (Some(&value_type), None) => value_type,
+            _ => "mixed",
+        };
+        let mut attribute = json!({
+            "attribute": name,
+            "count": self.values.len(),
+            "type": value_type,
+            "values": self.values,
+        });
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            attribute["min"] = json!(min);
+            attribute["max"] = json!(max);
+        }
+        attribute
+    }
+}
+
+#[derive(Debug, Default)]
+struct LayerStats {
+    count: u64,
+    geometries: BTreeMap<&'static str, u64>,
+    attributes: BTreeMap<String, AttributeStats>,
+}
+
+/// Accumulates tilestats (layer feature counts, geometry types and attributes) over decoded tiles.
+#[derive(Debug)]
+pub struct Tilestats {
+    max_values: usize,
+    layers: BTreeMap<String, LayerStats>,
+}
+
+fn geometry_name(geom_type: GeomType) -> &'static str {
+    match geom_type {
+        GeomType::Point => "Point",
+        GeomType::LineString => "LineString",
+        GeomType::Polygon => "Polygon",
+        GeomType::Unknown => "Unknown",
+    }
+}
+
+impl Tilestats {
+    pub fn new(max_values: usize) -> Self {
+        Tilestats { max_values, layers: BTreeMap::new() }
+    }
+
+    pub fn add_tile(&mut self, tile: &Tile) {
+        for layer in &tile.layers {
+            let stats = self.layers.entry(layer.name.clone()).or_default();
+            for feature in &layer.features {
+                stats.count += 1;
+                *stats.geometries.entry(geometry_name(feature.geom_type)).or_default() += 1;
+                for (key, value) in &feature.properties {
+                    stats.attributes.entry(key.clone()).or_default().add(value, self.max_values);
+                }
+            }
+        }
+    }
+
+    /// The `tilestats` object: layers by name, each with its most common geometry type.
+    pub fn to_json(&self) -> JsonValue {
+        let layers: Vec<JsonValue> = self.layers.iter().map(|(name, stats)| {
+            let geometry = stats.geometries.iter().max_by_key(|&(_, count)| count).map_or("Unknown", |(geometry, _)| geometry);
+            let attributes: Vec<JsonValue> = stats.attributes.iter().map(|(key, attribute)| attribute.to_json(key)).collect();
+            json!({
+                "layer": name,
+                "count": stats.count,
+                "geometry": geometry,
+                "attributeCount": attributes.len(),
+                "attributes": attributes,
+            })
+        }).collect();
+        json!({ "layerCount": layers.len(), "layers": layers })
+    }
+}
+
+/// Returns a copy of `metadata` with its `tilestats` replaced.
+pub fn set_tilestats(metadata: &Metadata, tilestats: &JsonValue) -> io::Result<Metadata> {
+    let mut json: JsonValue = serde_json::from_str(metadata.json())?;
+    let object = json.as_object_mut()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Metadata is not a JSON object"))?;
+    object.insert("tilestats".to_string(), tilestats.clone());
+    Metadata::from_json(&json.to_string())
+}
+
+impl PMTiles {
+    /// Decodes every distinct MVT tile and computes tilestats. A feature stored in
+    /// several tiles (e.g. at several zooms) is counted once per tile.
+    pub fn compute_tilestats(&self, max_values: usize) -> io::Result<JsonValue> {
+        if self.header.tile_type != TileType::MVT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Tilestats can only be computed for MVT archives, not {}", self.header.tile_type)
+            ));
+        }
+        let mut tilestats = Tilestats::new(max_values);
+        let mut seen = HashSet::new();
+        for entry in self.tile_entries()? {
+            if !seen.insert(entry.offset) {
+                continue;
+            }
+            let data = decompress(self.tile_data(&entry)?, self.header.tile_compression)?;
+            tilestats.add_tile(&Tile::decode(&data)?);
+        }
+        Ok(tilestats.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mvt::{Feature, Layer};
+    use crate::pmtiles::compression::compress;
+    use crate::pmtiles::edit::{HeaderEdit, edit_in_place};
+    use crate::pmtiles::testing::{TempPath, archive_bytes, test_header};
+    use crate::pmtiles::types::Compression;
+    use crate::tileid::TileId;
+
+    fn feature(geom_type: GeomType, properties: Vec<(&str, Value)>) -> Feature {
+        Feature {
+            properties: properties.into_iter().map(|(key, value)| (key.to_string(), value)).collect(),
+            geom_type,
+            geometry: vec![vec![(0, 0), (1, 1), (0, 1)]],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compute_and_write_tilestats() {
+        let file_path = TempPath::new("tilestats");
+
+        let mut road = Layer::new("road");
+        road.features.push(feature(GeomType::LineString, vec![("name", Value::String("a".to_string())), ("lanes", Value::Uint(2))]));
+        road.features.push(feature(GeomType::LineString, vec![("name", Value::String("b".to_string())), ("lanes", Value::Double(4.0))]));
+        road.features.push(feature(GeomType::Point, vec![("name", Value::String("c".to_string())), ("lanes", Value::String("?".to_string()))]));
+        let mut water = Layer::new("water");
+        water.features.push(feature(GeomType::Polygon, vec![("natural", Value::Bool(true))]));
+        let tiles = [Tile { layers: vec![road, water.clone()] }, Tile { layers: vec![water] }];
+
+        let header = test_header(TileType::MVT, Compression::Gzip);
+        let tiles = tiles.iter().enumerate().map(|(i, tile)| (TileId::new(i as u64), compress(&tile.encode(), Compression::Gzip).unwrap()));
+        std::fs::write(&file_path, archive_bytes(header, r#"{"name":"test"}"#, tiles)).unwrap();
+
+        let pmtiles = PMTiles::open(file_path.as_str()).unwrap();
+        let tilestats = pmtiles.compute_tilestats(2).unwrap();
+        assert_eq!(tilestats["layerCount"], 2);
+        let road = &tilestats["layers"][0];
+        assert_eq!((&road["layer"], &road["count"], &road["geometry"], &road["attributeCount"]), (&json!("road"), &json!(3), &json!("LineString"), &json!(2)));
+        assert_eq!(road["attributes"][0], json!({"attribute": "lanes", "count": 2, "type": "mixed", "values": [2.0, 4.0], "min": 2.0, "max": 4.0}));
+        assert_eq!(road["attributes"][1], json!({"attribute": "name", "count": 2, "type": "string", "values": ["a", "b"]}));
+        assert_eq!(tilestats["layers"][1]["count"], 2);
+        assert_eq!(tilestats["layers"][1]["attributes"][0]["type"], "boolean");
+
+        let metadata = set_tilestats(&pmtiles.metadata, &tilestats).unwrap();
+        drop(pmtiles);
+        edit_in_place(file_path.as_str(), &HeaderEdit::default(), Some(&metadata)).unwrap();
+        let written: JsonValue = serde_json::from_str(PMTiles::open(file_path.as_str()).unwrap().metadata.json()).unwrap();
+        assert_eq!(written["name"], "test");
+        assert_eq!(written["tilestats"], tilestats);
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::io;
+
+use super::PMTiles;
+use super::compression::{compress, decompress};
+use super::types::Compression;
+use super::writer::write_to_file;
+use crate::tileid::TileId;
+
+/// Sizes before and after `transcode`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscodeReport {
+    pub input_size: u64,
+    pub output_size: u64,
+    pub input_tile_data_length: usize,
+    pub output_tile_data_length: usize,
+}
+
+impl TranscodeReport {
+    /// Bytes saved by the new archive; negative when it grew.
+    pub fn savings(&self) -> i64 {
+        self.input_size as i64 - self.output_size as i64
+    }
+
+    pub fn print_info(&self) {
+        println!("Transcode:");
+        println!("  Archive: {} -> {} bytes", self.input_size, self.output_size);
+        println!("  Tile Data: {} -> {} bytes", self.input_tile_data_length, self.output_tile_data_length);
+        println!("  Savings: {} bytes ({:.1}%)", self.savings(), self.savings() as f64 * 100.0 / self.input_size as f64);
+    }
+}
+
+/// Writes a copy of `input_path` whose tiles are recompressed with `tile_compression`
+/// and whose directories and metadata use `internal_compression`.
+pub fn transcode(input_path: &str, output_path: &str, tile_compression: Compression, internal_compression: Compression) -> io::Result<TranscodeReport> {
+    let input = PMTiles::open(input_path)?;
+    let entries = input.tile_entries()?;
+
+    let mut header = input.header.clone();
+    header.tile_compression = tile_compression;
+    header.internal_compression = internal_compression;
+
+    let output_header = write_to_file(output_path, header, &input.metadata, |writer| {
+        input.for_each_unique_tile(&entries, |data| {
+            compress(&decompress(data, input.header.tile_compression)?, tile_compression)
+        }, |entry, data| {
+            for i in 0..entry.run_length as u64 {
+                writer.add_tile(TileId::new(entry.tileid.value() + i), data)?;
+            }
+            Ok(())
+        })
+    })?;
+
+    Ok(TranscodeReport {
+        input_size: fs::metadata(input_path)?.len(),
+        output_size: fs::metadata(output_path)?.len(),
+        input_tile_data_length: input.header.tile_data_length,
+        output_tile_data_length: output_header.tile_data_length,
+    })
+}
+
+// 往復にzstdを使う
+#[cfg(all(test, feature = "zstd"))]
+mod tests {
+    use super::*;
+    use crate::pmtiles::testing::{TempPath, test_header, write_archive};
+    use crate::pmtiles::types::TileType;
+
+    #[test]
+    fn gzip_to_zstd_and_back() {
+        let temp_paths = ["gzip", "zstd", "none"].map(|name| TempPath::new(&format!("transcode_{}", name)));
+        let paths = temp_paths.each_ref().map(TempPath::as_str);
+
+        let tile = "layer ".repeat(100);
+        let gzipped = compress(tile.as_bytes(), Compression::Gzip).unwrap();
+        let header = test_header(TileType::MVT, Compression::Gzip);
+        write_archive(&temp_paths[0], header, "{\"name\":\"transcode\"}", (0..5).map(|i| (TileId::new(i), &gzipped)));
+
+        let report = transcode(paths[0], paths[1], Compression::Zstd, Compression::Zstd).unwrap();
+        assert_eq!(report.input_size, fs::metadata(paths[0]).unwrap().len());
+        transcode(paths[1], paths[2], Compression::None, Compression::None).unwrap();
+
+        let zstd = PMTiles::open(paths[1]).unwrap();
+        let none = PMTiles::open(paths[2]).unwrap();
+
+        assert_eq!(zstd.header.tile_compression, Compression::Zstd);
+        assert_eq!(zstd.header.internal_compression, Compression::Zstd);
+        assert_eq!(zstd.header.num_addressed_tiles, 5);
+        assert_eq!(zstd.metadata.json(), "{\"name\":\"transcode\"}");
+        let data = zstd.get_tile(1, 1, 0).unwrap().unwrap();
+        assert_eq!(decompress(data, Compression::Zstd).unwrap(), tile.as_bytes());
+
+        assert_eq!(none.get_tile(1, 0, 1).unwrap().unwrap(), tile.as_bytes());
+        assert_eq!(none.header.tile_data_length, tile.len());
+    }
+}
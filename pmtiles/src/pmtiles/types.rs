@@ -1,7 +1,8 @@
 use std::fmt;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Compression {
+    #[default]
     Unknown = 0x00,
     None = 0x01,
     Gzip = 0x02,
@@ -30,8 +31,10 @@ impl fmt::Display for Compression {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum TileType {
+    #[default]
     Unknown = 0x00,
     MVT = 0x01,
     PNG = 0x02,
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::io;
+
+use serde_json::Value;
+
+use super::Data;
+use super::header::Header;
+use super::metadata::Metadata;
+use super::types::{Compression, TileType};
+use super::writer::write_to_file;
+use crate::tileid::{MAX_ZOOM, TileId};
+
+// v2のヘッダ: "PM"(2) + version(u16) + metadata長(u32) + ルートディレクトリのエントリ数(u16)
+const V2_MAGIC_NUMBER: &[u8] = b"PM";
+const V2_HEADER_SIZE: usize = 10;
+const V2_ENTRY_SIZE: usize = 17;
+const V2_LEAF_FLAG: u8 = 0b1000_0000;
+
+/// Returns true if `data` starts with a PMTiles v2 header.
+pub fn is_v2(data: &[u8]) -> bool {
+    data.len() >= 4 && &data[0..2] == V2_MAGIC_NUMBER && u16::from_le_bytes([data[2], data[3]]) == 2
+}
+
+fn to_uint_le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+/// A v2 directory. Entries are keyed by z/x/y; leaf directory pointers are kept
+/// separately, all at the same zoom level.
+#[derive(Debug, Default)]
+pub struct V2Directory {
+    pub tiles: HashMap<(u8, u32, u32), (usize, usize)>,
+    pub leaves: HashMap<(u8, u32, u32), (usize, usize)>,
+}
+
+impl V2Directory {
+    /// Parses 17-byte entries: z(1), x(3), y(3), offset(6), length(4), all little-endian.
+    /// The top bit of z marks a pointer to a leaf directory.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        if !data.len().is_multiple_of(V2_ENTRY_SIZE) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("v2 directory length {} is not a multiple of {}", data.len(), V2_ENTRY_SIZE)
+            ));
+        }
+
+        let mut directory = V2Directory::default();
+        for entry in data.chunks_exact(V2_ENTRY_SIZE) {
+            let z = entry[0];
+            let x = to_uint_le(&entry[1..4]) as u32;
+            let y = to_uint_le(&entry[4..7]) as u32;
+            let offset = to_uint_le(&entry[7..13]) as usize;
+            let length = to_uint_le(&entry[13..17]) as usize;
+            if z & V2_LEAF_FLAG != 0 {
+                directory.leaves.insert((z & !V2_LEAF_FLAG, x, y), (offset, length));
+            } else {
+                directory.tiles.insert((z, x, y), (offset, length));
+            }
+        }
+        Ok(directory)
+    }
+
+    fn leaf_zoom(&self) -> Option<u8> {
+        self.leaves.keys().next().map(|&(z, _, _)| z)
+    }
+}
+
+#[derive(Debug)]
+pub struct PMTilesV2 {
+    data: Data,
+    pub metadata: Metadata,
+    pub root_directory: V2Directory,
+}
+
+impl PMTilesV2 {
+    pub fn open(file_path: &str) -> io::Result<Self> {
+        PMTilesV2::parse(Data::open(file_path)?)
+    }
+
+    fn parse(data: Data) -> io::Result<Self> {
+        if data.len() < V2_HEADER_SIZE || !is_v2(&data) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a PMTiles v2 archive"));
+        }
+        let metadata_length = to_uint_le(&data[4..8]) as usize;
+        let root_entries = to_uint_le(&data[8..10]) as usize;
+
+        let metadata_end = V2_HEADER_SIZE + metadata_length;
+        let root_end = metadata_end + root_entries * V2_ENTRY_SIZE;
+        if data.len() < root_end {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "v2 header is truncated"));
+        }
+
+        let metadata = std::str::from_utf8(&data[V2_HEADER_SIZE..metadata_end])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let metadata = Metadata::from_json(metadata)?;
+        let root_directory = V2Directory::parse(&data[metadata_end..root_end])?;
+
+        Ok(PMTilesV2 { data, metadata, root_directory })
+    }
+
+    /// Looks up a tile, following the leaf directory at the archive's leaf zoom level.
+    pub fn get_tile(&self, z: u8, x: u32, y: u32) -> io::Result<Option<&[u8]>> {
+        if z > MAX_ZOOM {
+            return Ok(None);
+        }
+        if let Some(&(offset, length)) = self.root_directory.tiles.get(&(z, x, y)) {
+            return self.slice(offset, length).map(Some);
+        }
+
+        let leaf_zoom = match self.root_directory.leaf_zoom() {
+            Some(leaf_zoom) if z >= leaf_zoom => leaf_zoom,
+            _ => return Ok(None),
+        };
+        let shift = z - leaf_zoom;
+        let leaf_key = (leaf_zoom, x >> shift, y >> shift);
+        let Some(&(offset, length)) = self.root_directory.leaves.get(&leaf_key) else {
+            return Ok(None);
+        };
+
+        let leaf = V2Directory::parse(self.slice(offset, length)?)?;
+        match leaf.tiles.get(&(z, x, y)) {
+            Some(&(offset, length)) => self.slice(offset, length).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Collects every tile of the root and leaf directories as (TileId, offset, length),
+    /// sorted by TileId.
+    pub fn tiles(&self) -> io::Result<Vec<(TileId, usize, usize)>> {
+        let mut tiles: Vec<_> = self.root_directory.tiles.iter()
+            .map(|(&(z, x, y), &(offset, length))| (TileId::encode(z, x, y), offset, length))
+            .collect();
+        for &(offset, length) in self.root_directory.leaves.values() {
+            let leaf = V2Directory::parse(self.slice(offset, length)?)?;
+            tiles.extend(leaf.tiles.iter()
+                .map(|(&(z, x, y), &(offset, length))| (TileId::encode(z, x, y), offset, length)));
+        }
+        tiles.sort_by_key(|(tile_id, _, _)| tile_id.value());
+        Ok(tiles)
+    }
+
+    fn slice(&self, offset: usize, length: usize) -> io::Result<&[u8]> {
+        offset.checked_add(length)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Range {}+{} is outside of the archive ({} bytes)", offset, length, self.data.len())
+            ))
+    }
+}
+
+fn as_u8(value: &Value) -> Option<u8> {
+    match value {
+        Value::Number(n) => n.as_u64().map(|n| n as u8),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_f64_list(value: &Value) -> Vec<f64> {
+    value.as_str()
+        .map(|s| s.split(',').filter_map(|v| v.trim().parse().ok()).collect())
+        .unwrap_or_default()
+}
+
+/// Builds a v3 header from the v2 metadata keys that moved into the header in v3,
+/// removing them from `metadata`.
+fn header_from_v2_metadata(metadata: &mut serde_json::Map<String, Value>, tiles: &[(TileId, usize, usize)]) -> Header {
+    let tile_type = match metadata.remove("format").as_ref().and_then(Value::as_str) {
+        Some("pbf") | Some("mvt") => TileType::MVT,
+        Some("png") => TileType::PNG,
+        Some("jpg") | Some("jpeg") => TileType::JPEG,
+        Some("webp") => TileType::WebP,
+        _ => TileType::Unknown,
+    };
+    let tile_compression = match metadata.remove("compression").as_ref().and_then(Value::as_str) {
+        Some("gzip") => Compression::Gzip,
+        Some("br") | Some("brotli") => Compression::Brotli,
+        Some("zstd") => Compression::Zstd,
+        _ => Compression::None,
+    };
+
+    let zooms = tiles.iter().map(|(tile_id, _, _)| tile_id.decode().0);
+    let min_zoom = metadata.remove("minzoom").as_ref().and_then(as_u8)
+        .unwrap_or_else(|| zooms.clone().min().unwrap_or(0));
+    let max_zoom = metadata.remove("maxzoom").as_ref().and_then(as_u8)
+        .unwrap_or_else(|| zooms.max().unwrap_or(0));
+
+    let (min_position, max_position) = match as_f64_list(&metadata.remove("bounds").unwrap_or_default())[..] {
+        [min_lon, min_lat, max_lon, max_lat] => ((min_lon, min_lat), (max_lon, max_lat)),
+        _ => ((-180.0, -85.0), (180.0, 85.0)),
+    };
+    let (center_position, center_zoom) = match as_f64_list(&metadata.remove("center").unwrap_or_default())[..] {
+        [lon, lat, zoom] => ((lon, lat), zoom as u8),
+        _ => (((min_position.0 + max_position.0) / 2.0, (min_position.1 + max_position.1) / 2.0), min_zoom),
+    };
+
+    Header {
+        internal_compression: Compression::Gzip,
+        tile_compression,
+        tile_type,
+        min_zoom,
+        max_zoom,
+        min_position,
+        max_position,
+        center_zoom,
+        center_position,
+        ..Default::default()
+    }
+}
+
+/// Converts a v2 archive into a v3 archive at `output_path`.
+pub fn upgrade_to_v3(input_path: &str, output_path: &str) -> io::Result<Header> {
+    let v2 = PMTilesV2::open(input_path)?;
+    let tiles = v2.tiles()?;
+
+    let mut metadata: serde_json::Map<String, Value> = serde_json::from_str(v2.metadata.json())?;
+    let header = header_from_v2_metadata(&mut metadata, &tiles);
+    let metadata = Metadata::from_json(&Value::Object(metadata).to_string())?;
+
+    write_to_file(output_path, header, &metadata, |writer| {
+        for &(tile_id, offset, length) in &tiles {
+            writer.add_tile(tile_id, v2.slice(offset, length)?)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pmtiles::PMTiles;
+    use crate::pmtiles::testing::TempPath;
+
+    fn entry(z: u8, x: u32, y: u32, offset: usize, length: usize) -> Vec<u8> {
+        let mut bytes = vec![z];
+        bytes.extend_from_slice(&x.to_le_bytes()[..3]);
+        bytes.extend_from_slice(&y.to_le_bytes()[..3]);
+        bytes.extend_from_slice(&(offset as u64).to_le_bytes()[..6]);
+        bytes.extend_from_slice(&(length as u32).to_le_bytes());
+        bytes
+    }
+
+    /// Root: z0 tile and a leaf pointer at z1 (1,0). Leaf: z1 (1,0) and z2 (3,1).
+    fn write_v2_archive(file_path: &TempPath) {
+        let metadata = b"{\"name\":\"v2\",\"format\":\"png\",\"minzoom\":\"0\",\"maxzoom\":\"2\",\"bounds\":\"-180,-85,180,85\",\"center\":\"0,0,1\"}";
+        let root_offset = V2_HEADER_SIZE + metadata.len();
+        let tile_offset = root_offset + 2 * V2_ENTRY_SIZE;
+        let tiles: [&[u8]; 3] = [b"tile-z0", b"tile-z1", b"tile-z2"];
+        let leaf_offset = tile_offset + tiles.iter().map(|t| t.len()).sum::<usize>();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"PM");
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        data.extend_from_slice(&2u16.to_le_bytes());
+        data.extend_from_slice(metadata);
+        data.extend(entry(0, 0, 0, tile_offset, tiles[0].len()));
+        data.extend(entry(1 | V2_LEAF_FLAG, 1, 0, leaf_offset, 2 * V2_ENTRY_SIZE));
+        for tile in tiles {
+            data.extend_from_slice(tile);
+        }
+        data.extend(entry(1, 1, 0, tile_offset + 7, tiles[1].len()));
+        data.extend(entry(2, 3, 1, tile_offset + 14, tiles[2].len()));
+
+        std::fs::write(file_path, data).unwrap();
+    }
+
+    #[test]
+    fn read_v2_and_upgrade() {
+        let (input_path, output_path) = (TempPath::new("v2"), TempPath::new("v2_upgraded"));
+        let (input, output) = (input_path.as_str(), output_path.as_str());
+        write_v2_archive(&input_path);
+
+        let v2 = PMTilesV2::open(input).unwrap();
+        assert_eq!(v2.get_tile(0, 0, 0).unwrap(), Some(&b"tile-z0"[..]));
+        assert_eq!(v2.get_tile(1, 1, 0).unwrap(), Some(&b"tile-z1"[..]));
+        assert_eq!(v2.get_tile(2, 3, 1).unwrap(), Some(&b"tile-z2"[..]));
+        assert_eq!(v2.get_tile(2, 0, 0).unwrap(), None);
+        assert!(PMTiles::open(input).is_err());
+
+        let header = upgrade_to_v3(input, output).unwrap();
+        let v3 = PMTiles::open(output).unwrap();
+
+        assert_eq!(header.tile_type, TileType::PNG);
+        assert_eq!((header.min_zoom, header.max_zoom, header.center_zoom), (0, 2, 1));
+        assert_eq!(v3.header.num_addressed_tiles, 3);
+        assert_eq!(v3.get_tile(0, 0, 0).unwrap(), Some(&b"tile-z0"[..]));
+        assert_eq!(v3.get_tile(1, 1, 0).unwrap(), Some(&b"tile-z1"[..]));
+        assert_eq!(v3.get_tile(2, 3, 1).unwrap(), Some(&b"tile-z2"[..]));
+        assert_eq!(v3.metadata.json(), "{\"name\":\"v2\"}");
+    }
+
+    #[test]
+    fn zoom_beyond_max_is_missing() {
+        let input = TempPath::new("v2_deep");
+        write_v2_archive(&input);
+        let v2 = PMTilesV2::open(input.as_str()).unwrap();
+
+        // リーフのズーム(1)から32以上深いとシフトが溢れる
+        assert_eq!(v2.get_tile(MAX_ZOOM + 2, 0, 0).unwrap(), None);
+        assert_eq!(v2.get_tile(u8::MAX, u32::MAX, u32::MAX).unwrap(), None);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use super::directory::{Directory, DirectoryEntry};
+use super::header::{Header, HEADER_SIZE};
+use super::metadata::Metadata;
+use crate::tileid::TileId;
+
+/// Two independent 64-bit hashes plus the length, used to find duplicate tile contents.
+type ContentKey = (u64, u64, usize);
+
+fn content_key(data: &[u8]) -> ContentKey {
+    let mut first = DefaultHasher::new();
+    data.hash(&mut first);
+    let mut second = DefaultHasher::new();
+    0xffu8.hash(&mut second);
+    data.hash(&mut second);
+    (first.finish(), second.finish(), data.len())
+}
+
+/// Builds a PMTiles v3 archive.
+///
+/// Tiles must be added in ascending TileID order. Tile data is appended to `spool` as it
+/// arrives, identical contents are stored only once and consecutive TileIDs sharing the
+/// same contents are merged into a single run-length entry. `finish` then writes the
+/// header, directories, metadata and the spooled tile data to the output.
+pub struct Writer<S: Read + Write + Seek> {
+    spool: S,
+    tile_data_length: usize,
+    entries: Vec<DirectoryEntry>,
+    contents: HashMap<ContentKey, (usize, usize)>,
+    num_addressed_tiles: u64,
+}
+
+impl<S: Read + Write + Seek> Writer<S> {
+    pub fn new(spool: S) -> Self {
+        Writer {
+            spool,
+            tile_data_length: 0,
+            entries: Vec::new(),
+            contents: HashMap::new(),
+            num_addressed_tiles: 0,
+        }
+    }
+
+    pub fn add_tile(&mut self, tile_id: TileId, data: &[u8]) -> io::Result<()> {
+        if let Some(last) = self.entries.last()
+            && tile_id.value() < last.tileid.value() + last.run_length as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Tiles must be added in ascending TileID order: {} after {}", tile_id.value(), last.tileid.value())
+            ));
+        }
+        self.num_addressed_tiles += 1;
+
+        let key = content_key(data);
+        let (offset, length) = match self.contents.get(&key) {
+            Some(&location) => location,
+            None => {
+                let location = (self.tile_data_length, data.len());
+                self.spool.write_all(data)?;
+                self.tile_data_length += data.len();
+                self.contents.insert(key, location);
+                location
+            }
+        };
+
+        if let Some(last) = self.entries.last_mut()
+            && last.tileid.value() + last.run_length as u64 == tile_id.value()
+            && last.offset == offset && last.length == length {
+            last.run_length += 1;
+            return Ok(());
+        }
+        self.entries.push(DirectoryEntry::new(tile_id, offset, length, 1));
+        Ok(())
+    }
+
+    /// Writes the archive to `out`. Tile type, compressions, zooms, bounds and center are
+    /// taken from `header`; section offsets and tile counts are filled in here.
+    pub fn finish<W: Write>(mut self, out: &mut W, mut header: Header, metadata: &Metadata) -> io::Result<Header> {
+        let layout = Directory::build_layout(&self.entries, header.internal_compression)?;
+        let metadata = metadata.to_compressed(header.internal_compression)?;
+
+        header.version = 3;
+        header.root_dir_offset = HEADER_SIZE;
+        header.root_dir_length = layout.root.len();
+        header.metadata_offset = header.root_dir_offset + header.root_dir_length;
+        header.metadata_length = metadata.len();
+        header.leaf_dirs_offset = header.metadata_offset + header.metadata_length;
+        header.leaf_dirs_length = layout.leaves.len();
+        header.tile_data_offset = header.leaf_dirs_offset + header.leaf_dirs_length;
+        header.tile_data_length = self.tile_data_length;
+        header.num_addressed_tiles = self.num_addressed_tiles;
+        header.num_tile_entries = self.entries.len() as u64;
+        header.num_tile_contents = self.contents.len() as u64;
+        // タイルはTileID順に追加されるので常にclusteredになる
+        header.clustered = 1;
+
+        out.write_all(&header.to_bytes())?;
+        out.write_all(&layout.root)?;
+        out.write_all(&metadata)?;
+        out.write_all(&layout.leaves)?;
+
+        self.spool.seek(SeekFrom::Start(0))?;
+        io::copy(&mut self.spool.take(self.tile_data_length as u64), out)?;
+        out.flush()?;
+
+        Ok(header)
+    }
+}
+
+/// Writes an archive to `output_path`, spooling tile data to a temporary file next to it.
+/// `fill` adds the tiles; the spool file is removed whether or not it succeeds.
+pub fn write_to_file<F>(output_path: &str, header: Header, metadata: &Metadata, fill: F) -> io::Result<Header>
+where
+    F: FnOnce(&mut Writer<File>) -> io::Result<()>,
+{
+    let spool_path = format!("{}.tiles.tmp", output_path);
+    let spool = File::options().read(true).write(true).create(true).truncate(true).open(&spool_path)?;
+
+    let result = (|| {
+        let mut writer = Writer::new(spool);
+        fill(&mut writer)?;
+        let mut out = io::BufWriter::new(File::create(output_path)?);
+        writer.finish(&mut out, header, metadata)
+    })();
+
+    // 後始末の失敗で本来の結果を隠さない
+    if let Err(e) = fs::remove_file(&spool_path) {
+        log::warn!("Failed to remove {}: {}", spool_path, e);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::pmtiles::types::{Compression, TileType};
+
+    #[test]
+    fn write_dedup_and_run_length() {
+        let mut writer = Writer::new(Cursor::new(Vec::new()));
+        writer.add_tile(TileId::new(0), b"root").unwrap();
+        writer.add_tile(TileId::new(1), b"ocean").unwrap();
+        writer.add_tile(TileId::new(2), b"ocean").unwrap();
+        writer.add_tile(TileId::new(3), b"land").unwrap();
+        writer.add_tile(TileId::new(5), b"ocean").unwrap();
+        assert!(writer.add_tile(TileId::new(4), b"late").is_err());
+
+        let header = Header {
+            internal_compression: Compression::Gzip,
+            tile_compression: Compression::None,
+            tile_type: TileType::MVT,
+            ..Default::default()
+        };
+        let metadata = Metadata::from_json("{\"name\":\"test\"}").unwrap();
+        let mut out = Vec::new();
+        let header = writer.finish(&mut out, header, &metadata).unwrap();
+
+        assert_eq!(header.num_addressed_tiles, 5);
+        assert_eq!(header.num_tile_entries, 4);
+        assert_eq!(header.num_tile_contents, 3);
+        assert_eq!(header.tile_data_length, 13);
+        assert_eq!(out.len(), header.tile_data_offset + header.tile_data_length);
+        assert_eq!(Header::parse(&out[..HEADER_SIZE]).unwrap().to_bytes(), header.to_bytes());
+
+        let root = &out[header.root_dir_offset .. header.root_dir_offset + header.root_dir_length];
+        let root = Directory::parse_compressed(root, Compression::Gzip).unwrap();
+        assert_eq!(root.entries[1].run_length, 2);
+        assert_eq!(root.entries[3].offset, root.entries[1].offset);
+    }
+}
@@ -1,18 +1,28 @@
+/// Longest varint encoding of a u64.
+pub const MAX_VARINT_LEN: usize = 10;
 
+/// Decodes a varint, returning the value and the number of bytes read.
+/// Only the shortest encoding of each value is accepted.
 pub fn decode_varint(data: &[u8]) -> Result<(u64, usize), &'static str> {
     let mut result = 0u64;
     let mut shift = 0;
 
     for (i, &byte) in data.iter().enumerate() {
-        if shift >= 64 {
+        if i == MAX_VARINT_LEN {
             return Err("Varint is too long");
         }
-        //println!("byte: {:08b}", byte);
 
         let value = (byte & 0b0111_1111) as u64;
+        // 10バイト目に使えるのは最上位の1bitだけ
+        if i == MAX_VARINT_LEN - 1 && value > 1 {
+            return Err("Varint overflows 64 bits");
+        }
         result |= value << shift;
 
         if byte & 0b1000_0000 == 0 {
+            if byte == 0 && i > 0 {
+                return Err("Overlong varint encoding");
+            }
             return Ok((result, i + 1));
         }
         shift += 7;
@@ -20,9 +30,83 @@ pub fn decode_varint(data: &[u8]) -> Result<(u64, usize), &'static str> {
     Err("Incomplete varint data")
 }
 
+pub fn encode_varint(mut value: u64, buffer: &mut Vec<u8>) {
+    while value >= 0b1000_0000 {
+        buffer.push((value as u8 & 0b0111_1111) | 0b1000_0000);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+/// Maps signed to unsigned so that small magnitudes get short varints (sint32).
+pub fn zigzag_encode_32(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+pub fn zigzag_decode_32(value: u32) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+/// 64-bit variant of `zigzag_encode_32` (sint64).
+pub fn zigzag_encode_64(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+pub fn zigzag_decode_64(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Reads consecutive varints from a byte slice, keeping track of the position.
+#[derive(Debug, Clone)]
+pub struct VarintReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> VarintReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        VarintReader { data, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64, &'static str> {
+        let (value, size) = decode_varint(&self.data[self.position..])?;
+        self.position += size;
+        Ok(value)
+    }
+
+    pub fn read_sint32(&mut self) -> Result<i32, &'static str> {
+        let value = u32::try_from(self.read_varint()?).map_err(|_| "Varint overflows 32 bits")?;
+        Ok(zigzag_decode_32(value))
+    }
+
+    pub fn read_sint64(&mut self) -> Result<i64, &'static str> {
+        Ok(zigzag_decode_64(self.read_varint()?))
+    }
+
+    /// Reads `length` raw bytes, e.g. the payload of a length-delimited field.
+    pub fn read_bytes(&mut self, length: usize) -> Result<&'a [u8], &'static str> {
+        let bytes = self.position.checked_add(length)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or("Incomplete length-delimited data")?;
+        self.position += length;
+        Ok(bytes)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    // proptestはwasm32では使えない
+    #[cfg(not(target_arch = "wasm32"))]
+    use proptest::prelude::*;
 
     #[test]
     fn decode_150() {
@@ -30,4 +114,100 @@ mod tests {
         let result = decode_varint(&bytes);
         assert_eq!(result, Ok((150, 2)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn encode_150() {
+        let mut buffer = Vec::new();
+        encode_varint(150, &mut buffer);
+        assert_eq!(buffer, [0b1001_0110_u8, 0b0000_0001_u8]);
+    }
+
+    #[test]
+    fn byte_length_boundaries() {
+        // 長さnで表せる最大値と、n+1バイトになる最小値
+        for length in 1..=MAX_VARINT_LEN {
+            let max = if length == MAX_VARINT_LEN { u64::MAX } else { (1u64 << (7 * length)) - 1 };
+            let min = if length == 1 { 0 } else { 1u64 << (7 * (length - 1)) };
+            for value in [min, max] {
+                let mut buffer = Vec::new();
+                encode_varint(value, &mut buffer);
+                assert_eq!(buffer.len(), length, "value {}", value);
+                assert_eq!(decode_varint(&buffer), Ok((value, length)));
+            }
+        }
+    }
+
+    #[test]
+    fn u64_max() {
+        let mut buffer = Vec::new();
+        encode_varint(u64::MAX, &mut buffer);
+        assert_eq!(buffer, [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        assert_eq!(decode_varint(&buffer), Ok((u64::MAX, 10)));
+    }
+
+    #[test]
+    fn rejects_invalid_encodings() {
+        assert_eq!(decode_varint(&[]), Err("Incomplete varint data"));
+        assert_eq!(decode_varint(&[0x80, 0x80]), Err("Incomplete varint data"));
+        assert_eq!(decode_varint(&[0x80, 0x00]), Err("Overlong varint encoding"));
+        assert_eq!(decode_varint(&[0x96, 0x81, 0x00]), Err("Overlong varint encoding"));
+        assert_eq!(decode_varint(&[0xff; 9].iter().chain(&[0x02]).copied().collect::<Vec<_>>()), Err("Varint overflows 64 bits"));
+        assert_eq!(decode_varint(&[0x80; 11]), Err("Varint is too long"));
+    }
+
+    #[test]
+    fn zigzag() {
+        let cases_32 = [(0, 0), (-1, 1), (1, 2), (-2, 3), (i32::MAX, u32::MAX - 1), (i32::MIN, u32::MAX)];
+        for (signed, unsigned) in cases_32 {
+            assert_eq!(zigzag_encode_32(signed), unsigned);
+            assert_eq!(zigzag_decode_32(unsigned), signed);
+        }
+        let cases_64 = [(0, 0), (-1, 1), (1, 2), (-2, 3), (i64::MAX, u64::MAX - 1), (i64::MIN, u64::MAX)];
+        for (signed, unsigned) in cases_64 {
+            assert_eq!(zigzag_encode_64(signed), unsigned);
+            assert_eq!(zigzag_decode_64(unsigned), signed);
+        }
+    }
+
+    #[test]
+    fn varint_reader() {
+        let mut buffer = Vec::new();
+        encode_varint(150, &mut buffer);
+        encode_varint(zigzag_encode_32(-75) as u64, &mut buffer);
+        encode_varint(zigzag_encode_64(i64::MIN), &mut buffer);
+        buffer.extend(b"abc");
+        encode_varint(u64::MAX, &mut buffer);
+
+        let mut reader = VarintReader::new(&buffer);
+        assert_eq!(reader.read_varint(), Ok(150));
+        assert_eq!(reader.read_sint32(), Ok(-75));
+        assert_eq!(reader.read_sint64(), Ok(i64::MIN));
+        assert_eq!(reader.read_bytes(3), Ok(&b"abc"[..]));
+        assert_eq!(reader.read_sint32(), Err("Varint overflows 32 bits"));
+        assert!(reader.is_empty());
+        assert_eq!(reader.position(), buffer.len());
+        assert_eq!(reader.read_varint(), Err("Incomplete varint data"));
+        assert_eq!(reader.read_bytes(1), Err("Incomplete length-delimited data"));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    proptest! {
+        #[test]
+        fn varint_round_trip(value in any::<u64>()) {
+            let mut buffer = Vec::new();
+            encode_varint(value, &mut buffer);
+            prop_assert_eq!(decode_varint(&buffer), Ok((value, buffer.len())));
+        }
+
+        #[test]
+        fn zigzag_round_trip(value_32 in any::<i32>(), value_64 in any::<i64>()) {
+            prop_assert_eq!(zigzag_decode_32(zigzag_encode_32(value_32)), value_32);
+            prop_assert_eq!(zigzag_decode_64(zigzag_encode_64(value_64)), value_64);
+        }
+
+        #[test]
+        fn decode_never_panics(data in prop::collection::vec(any::<u8>(), 0..16)) {
+            let _ = decode_varint(&data);
+        }
+    }
+}
@@ -1,4 +1,9 @@
 #![allow(unused)]
+/// Deepest zoom level a 64-bit TileID can address.
+pub const MAX_ZOOM: u8 = 31;
+/// One past the last TileID of `MAX_ZOOM`, i.e. (4^32 - 1) / 3.
+pub const MAX_TILE_ID_END: u64 = u64::MAX / 3;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TileId {
     value: u64,
@@ -36,7 +41,7 @@ impl TileId {
             let ry = ((y & s) > 0) as u32;
             d += (s as u64) * (s as u64) * ((3 * rx) ^ ry) as u64;
             Self::rotate(n, &mut x, &mut y, rx, ry);
-            s = s >> 1;
+            s >>= 1;
         }
         d
     }
@@ -57,6 +62,21 @@ impl TileId {
         (z, x, y)
     } 
 
+    /// The tile one zoom level up that contains this one, or `None` at zoom 0.
+    pub fn parent(&self) -> Option<TileId> {
+        let (z, x, y) = self.decode();
+        if z == 0 {
+            return None;
+        }
+        Some(TileId::encode(z - 1, x / 2, y / 2))
+    }
+
+    /// The four tiles one zoom level down, in the order top-left, top-right, bottom-left, bottom-right.
+    pub fn children(&self) -> [TileId; 4] {
+        let (z, x, y) = self.decode();
+        [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| TileId::encode(z + 1, x * 2 + dx, y * 2 + dy))
+    }
+
     // ヒルベルト曲線
     fn hilbert_to_xy(z: u8, mut d: u64) -> (u32, u32) {
         /* 1. 処理の全体像
@@ -106,7 +126,7 @@ impl TileId {
             Self::rotate(s, &mut x, &mut y, rx, ry);
             x += s * rx;
             y += s * ry;
-            d = d >> 2; // 2bit右シフト
+            d >>= 2; // 2bit右シフト
             s *= 2;
         }
         (x, y)
@@ -146,6 +166,33 @@ impl TileId {
     }
 }
 
+/// Returns the x/y of the tile at zoom `z` containing the given WGS84 position
+/// (Web Mercator, y grows southwards).
+pub fn lon_lat_to_xy(z: u8, lon: f64, lat: f64) -> (u32, u32) {
+    let (x, y) = lon_lat_to_tile_position(z, lon, lat);
+    let max = ((1u64 << z) - 1) as f64;
+    (x.floor().clamp(0.0, max) as u32, y.floor().clamp(0.0, max) as u32)
+}
+
+/// Like `lon_lat_to_xy`, but keeps the fraction: the integer part is the tile and the
+/// fractional part the position within it.
+pub fn lon_lat_to_tile_position(z: u8, lon: f64, lat: f64) -> (f64, f64) {
+    let n = (1u64 << z) as f64;
+    let lat = lat.clamp(-85.051_128_78, 85.051_128_78).to_radians();
+    let x = (lon + 180.0) / 360.0 * n;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / std::f64::consts::PI) / 2.0 * n;
+    (x, y)
+}
+
+/// Inverse of `lon_lat_to_tile_position`: the WGS84 position of a (fractional) tile
+/// coordinate, e.g. `(x, y)` for the top-left corner of tile x/y.
+pub fn tile_position_to_lon_lat(z: u8, x: f64, y: f64) -> (f64, f64) {
+    let n = (1u64 << z) as f64;
+    let lon = x / n * 360.0 - 180.0;
+    let lat = (std::f64::consts::PI * (1.0 - 2.0 * y / n)).sinh().atan().to_degrees();
+    (lon, lat)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -173,4 +220,29 @@ mod tests {
         //assert_eq!(TileId::encode(16, 55234, 27904).value(), 1);
 
     }
+
+    #[test]
+    fn parent_and_children() {
+        assert_eq!(TileId::new(0).parent(), None);
+        assert_eq!(TileId::encode(12, 3423, 1763).parent(), Some(TileId::encode(11, 1711, 881)));
+        let tile_id = TileId::encode(3, 5, 2);
+        let children = tile_id.children();
+        assert_eq!(children[0].decode(), (4, 10, 4));
+        assert_eq!(children[3].decode(), (4, 11, 5));
+        assert!(children.iter().all(|child| child.parent() == Some(tile_id)));
+    }
+
+    #[test]
+    fn lon_lat_to_tile() {
+        assert_eq!(lon_lat_to_xy(0, 139.767125, 35.681236), (0, 0));
+        assert_eq!(lon_lat_to_xy(1, -0.5, 0.5), (0, 0));
+        assert_eq!(lon_lat_to_xy(1, 0.5, -0.5), (1, 1));
+        assert_eq!(lon_lat_to_xy(16, 139.767125, 35.681236), (58211, 25806));
+        assert_eq!(lon_lat_to_xy(2, 180.0, -90.0), (3, 3));
+
+        let (x, y) = lon_lat_to_tile_position(16, 139.767125, 35.681236);
+        let (lon, lat) = tile_position_to_lon_lat(16, x, y);
+        assert!((lon - 139.767125).abs() < 1e-9 && (lat - 35.681236).abs() < 1e-9);
+        assert_eq!(tile_position_to_lon_lat(1, 1.0, 1.0), (0.0, 0.0));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,64 @@
+//! `wasm-bindgen` bindings for reading an archive held in memory, e.g. from `fetch` or a file input.
+
+use serde_json::json;
+use wasm_bindgen::prelude::*;
+
+use crate::pmtiles::PMTiles;
+use crate::pmtiles::compression::decompress;
+
+#[wasm_bindgen]
+pub struct Reader {
+    pmtiles: PMTiles,
+}
+
+#[wasm_bindgen]
+impl Reader {
+    /// Parses a whole archive; the bytes are copied into WASM memory.
+    #[wasm_bindgen(constructor)]
+    pub fn new(data: Vec<u8>) -> Result<Reader, JsError> {
+        Ok(Reader { pmtiles: PMTiles::from_bytes(data)? })
+    }
+
+    /// The tile as stored, still compressed with the header's `tile_compression`.
+    #[wasm_bindgen(js_name = getTile)]
+    pub fn get_tile(&self, z: u8, x: u32, y: u32) -> Result<Option<Vec<u8>>, JsError> {
+        Ok(self.pmtiles.get_tile(z, x, y)?.map(<[u8]>::to_vec))
+    }
+
+    /// The tile with its tile compression removed.
+    #[wasm_bindgen(js_name = getTileDecompressed)]
+    pub fn get_tile_decompressed(&self, z: u8, x: u32, y: u32) -> Result<Option<Vec<u8>>, JsError> {
+        let Some(data) = self.pmtiles.get_tile(z, x, y)? else {
+            return Ok(None);
+        };
+        Ok(Some(decompress(data, self.pmtiles.header.tile_compression)?))
+    }
+
+    /// The header as a JSON string; positions are `[lon, lat]`.
+    #[wasm_bindgen(js_name = headerJson)]
+    pub fn header_json(&self) -> String {
+        let header = &self.pmtiles.header;
+        json!({
+            "version": header.version,
+            "numAddressedTiles": header.num_addressed_tiles,
+            "numTileEntries": header.num_tile_entries,
+            "numTileContents": header.num_tile_contents,
+            "clustered": header.clustered == 1,
+            "internalCompression": header.internal_compression.to_string(),
+            "tileCompression": header.tile_compression.to_string(),
+            "tileType": header.tile_type.to_string(),
+            "minZoom": header.min_zoom,
+            "maxZoom": header.max_zoom,
+            "minPosition": [header.min_position.0, header.min_position.1],
+            "maxPosition": [header.max_position.0, header.max_position.1],
+            "centerZoom": header.center_zoom,
+            "centerPosition": [header.center_position.0, header.center_position.1],
+        }).to_string()
+    }
+
+    /// The metadata JSON as stored in the archive.
+    #[wasm_bindgen(js_name = metadataJson)]
+    pub fn metadata_json(&self) -> String {
+        self.pmtiles.metadata.json().to_string()
+    }
+}
@@ -0,0 +1,41 @@
+//! Run under node with `cargo test --target wasm32-unknown-unknown --no-default-features --features wasm --test wasm`
+//! (needs `wasm-bindgen-test-runner` from wasm-bindgen-cli as the target runner).
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use std::io::Cursor;
+
+use serde_json::json;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use pmtiles::pmtiles::compression::compress;
+use pmtiles::pmtiles::header::Header;
+use pmtiles::pmtiles::metadata::Metadata;
+use pmtiles::pmtiles::types::{Compression, TileType};
+use pmtiles::pmtiles::writer::Writer;
+use pmtiles::tileid::TileId;
+use pmtiles::wasm::Reader;
+
+#[wasm_bindgen_test]
+fn read_archive_from_bytes() {
+    let mut writer = Writer::new(Cursor::new(Vec::new()));
+    for i in 0..5u64 {
+        writer.add_tile(TileId::new(i), &compress(&i.to_le_bytes(), Compression::Gzip).unwrap()).unwrap();
+    }
+    let header = Header {
+        internal_compression: Compression::Gzip,
+        tile_compression: Compression::Gzip,
+        tile_type: TileType::MVT,
+        max_zoom: 1,
+        ..Default::default()
+    };
+    let mut data = Vec::new();
+    writer.finish(&mut data, header, &Metadata::from_json(r#"{"name":"wasm"}"#).unwrap()).unwrap();
+
+    let reader = Reader::new(data).unwrap();
+    assert_eq!(reader.get_tile_decompressed(1, 1, 1).unwrap(), Some(3u64.to_le_bytes().to_vec()));
+    assert!(reader.get_tile(1, 1, 1).unwrap().is_some());
+    assert_eq!(reader.get_tile(2, 0, 0).unwrap(), None);
+    let header: serde_json::Value = serde_json::from_str(&reader.header_json()).unwrap();
+    assert_eq!((&header["tileType"], &header["maxZoom"]), (&json!("MVT"), &json!(1)));
+    assert_eq!(reader.metadata_json(), r#"{"name":"wasm"}"#);
+}